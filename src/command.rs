@@ -0,0 +1,129 @@
+// Parsing and completion for the `:` command line.
+
+pub const COMMANDS: &[&str] = &["bookmark", "help", "insert", "log", "quit", "rescan", "rpm", "seek", "volume"];
+
+pub enum Command {
+    Bookmark,
+    Help,
+    Insert(String),
+    Log,
+    Quit,
+    Rescan,
+    Rpm(f32),
+    Seek(Seek),
+    Volume(u32),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Seek {
+    To(f64),
+    Forward(f64),
+    Back(f64),
+}
+
+// Commands can be shortened to any unique prefix, like `:q` or `:vol 40`.
+fn resolve(name: &str) -> Result<&'static str, String> {
+    if let Some(exact) = COMMANDS.iter().find(|c| **c == name) {
+        return Ok(exact);
+    }
+    let matches: Vec<&'static str> = COMMANDS.iter().copied().filter(|c| c.starts_with(name)).collect();
+    match matches.as_slice() {
+        [one] => Ok(one),
+        [] => Err(format!("Unknown command '{}'", name)),
+        many => Err(format!("Ambiguous command '{}': {}", name, many.join(", "))),
+    }
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+    match resolve(name)? {
+        "bookmark" => Ok(Command::Bookmark),
+        "help" => Ok(Command::Help),
+        "log" => Ok(Command::Log),
+        "quit" => Ok(Command::Quit),
+        "rescan" => Ok(Command::Rescan),
+        "insert" if arg.is_empty() => Err("Usage: insert <album name>".to_string()),
+        "insert" => Ok(Command::Insert(arg.to_string())),
+        "volume" => match arg.parse::<u32>() {
+            Ok(v) if v <= 200 => Ok(Command::Volume(v)),
+            _ => Err("Usage: volume <0-200>".to_string()),
+        },
+        "rpm" => match arg {
+            "33" | "45" | "78" => Ok(Command::Rpm(arg.parse().unwrap_or(33.0))),
+            _ => Err("Usage: rpm <33|45|78>".to_string()),
+        },
+        "seek" => {
+            parse_seek(arg).map(Command::Seek).ok_or_else(|| "Usage: seek [+|-]<[h:]m:ss or seconds>".to_string())
+        }
+        _ => unreachable!(),
+    }
+}
+
+// A time to go to, or one to move by when it starts with `+` or `-`.
+pub fn parse_seek(arg: &str) -> Option<Seek> {
+    if let Some(rest) = arg.strip_prefix('+') {
+        parse_time(rest).map(Seek::Forward)
+    } else if let Some(rest) = arg.strip_prefix('-') {
+        parse_time(rest).map(Seek::Back)
+    } else {
+        parse_time(arg).map(Seek::To)
+    }
+}
+
+// "90", "1:30" and "1:01:30" are all accepted. `inf` and `nan` parse as
+// floats but are no time.
+fn parse_time(value: &str) -> Option<f64> {
+    let mut secs = 0.0;
+    for part in value.split(':') {
+        let n: f64 = part.trim().parse().ok()?;
+        if !n.is_finite() || n < 0.0 {
+            return None;
+        }
+        secs = secs * 60.0 + n;
+    }
+    Some(secs)
+}
+
+// Full-line candidates for Tab: command names for the first word, album
+// names after `insert`.
+pub fn complete(line: &str, albums: &[&str]) -> Vec<String> {
+    match line.split_once(' ') {
+        None => COMMANDS.iter().filter(|c| c.starts_with(line)).map(|c| c.to_string()).collect(),
+        Some((name, arg)) if resolve(name) == Ok("insert") => {
+            let needle = arg.trim_start().to_lowercase();
+            let mut names: Vec<&str> =
+                albums.iter().copied().filter(|a| a.to_lowercase().starts_with(&needle)).collect();
+            if names.is_empty() {
+                names = albums.iter().copied().filter(|a| a.to_lowercase().contains(&needle)).collect();
+            }
+            names.sort_unstable();
+            names.dedup();
+            names.into_iter().map(|a| format!("{} {}", name, a)).collect()
+        }
+        Some(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_times() {
+        assert_eq!(parse_seek("90"), Some(Seek::To(90.0)));
+        assert_eq!(parse_seek("1:30"), Some(Seek::To(90.0)));
+        assert_eq!(parse_seek("1:01:30"), Some(Seek::To(3690.0)));
+        assert_eq!(parse_seek("+10"), Some(Seek::Forward(10.0)));
+        assert_eq!(parse_seek("-5"), Some(Seek::Back(5.0)));
+        assert_eq!(parse_seek("1:-5"), None);
+        assert_eq!(parse_seek("inf"), None);
+        assert_eq!(parse_seek("+inf"), None);
+        assert_eq!(parse_seek("1:infinity"), None);
+        assert_eq!(parse_seek("nan"), None);
+        assert_eq!(parse_seek("-NaN"), None);
+    }
+}
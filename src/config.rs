@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::keymap::KeyList;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where marks are kept between runs, nothing is saved without it
+    pub state_file: Option<PathBuf>,
+    /// Listening log, one JSON line per track played, for the statistics
+    pub history_file: Option<PathBuf>,
+    /// Action name to key(s), e.g. `down = ["j", "<Down>"]`
+    pub keys: HashMap<String, KeyList>,
+    pub theme: ThemeConfig,
+    pub layout: LayoutConfig,
+    pub mpris: MprisConfig,
+    pub control: ControlConfig,
+    pub mpd: MpdConfig,
+    pub http: HttpConfig,
+    pub hooks: HooksConfig,
+    pub now_playing: NowPlayingConfig,
+    pub scrobble: ScrobbleConfig,
+    pub notifications: NotificationsConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// "default", "high-contrast" or "mono"
+    pub name: Option<String>,
+    pub title: Option<String>,
+    pub accent: Option<String>,
+    pub border: Option<String>,
+    pub muted: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    /// Width of the shelf in percent, the backside gets the rest
+    pub shelf_width: u16,
+    /// Height of the player panel in rows
+    pub player_height: u16,
+    pub show_header: bool,
+    pub show_player: bool,
+    pub show_backside: bool,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self { shelf_width: 40, player_height: 10, show_header: true, show_player: true, show_backside: true }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MprisConfig {
+    /// Register on the session bus for media keys, playerctl and status bars
+    pub enabled: bool,
+}
+
+impl Default for MprisConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    /// Listen on a Unix socket for `levari ctl`
+    pub enabled: bool,
+    /// Defaults to $XDG_RUNTIME_DIR/levari.sock
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self { enabled: true, socket: None }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MpdConfig {
+    /// Serve a subset of the MPD protocol for MPD clients
+    pub enabled: bool,
+    /// Host and port to listen on
+    pub address: String,
+}
+
+impl Default for MpdConfig {
+    fn default() -> Self {
+        Self { enabled: false, address: "127.0.0.1:6600".to_string() }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Serve the web remote and its JSON API
+    pub enabled: bool,
+    /// Host and port to listen on, only this machine can connect by default
+    pub address: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { enabled: false, address: "127.0.0.1:8333".to_string() }
+    }
+}
+
+// Shell commands run on player events, with LEVARI_* variables describing
+// the album and track.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    pub on_insert: Option<String>,
+    pub on_eject: Option<String>,
+    pub on_track_change: Option<String>,
+    pub on_pause: Option<String>,
+    pub on_resume: Option<String>,
+    pub on_bookmark: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NowPlayingConfig {
+    /// Rewritten with `format` whenever it changes, empty when stopped
+    pub text_file: Option<PathBuf>,
+    /// Everything the player panel shows, `null` when stopped
+    pub json_file: Option<PathBuf>,
+    pub format: String,
+}
+
+impl Default for NowPlayingConfig {
+    fn default() -> Self {
+        Self {
+            text_file: None,
+            json_file: None,
+            format: "{track}\n{artist}\n{album}\n{elapsed} / {duration}\n{cover}\n".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrobbleConfig {
+    /// Queue finished tracks and submit them
+    pub enabled: bool,
+    /// Root of a ListenBrainz compatible API
+    pub url: String,
+    /// User token, sent as `Authorization: Token <token>`
+    pub token: Option<String>,
+    /// Scrobbles waiting to be submitted, defaults to
+    /// $XDG_STATE_HOME/levari/scrobbles.jsonl
+    pub queue_file: Option<PathBuf>,
+}
+
+impl Default for ScrobbleConfig {
+    fn default() -> Self {
+        Self { enabled: false, url: "https://api.listenbrainz.org".to_string(), token: None, queue_file: None }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    /// Desktop notifications when an album is inserted or a track changes
+    pub enabled: bool,
+    /// Seconds between two notifications, quicker changes are merged
+    pub min_interval: f64,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self { enabled: false, min_interval: 2.0 }
+    }
+}
+
+impl ScrobbleConfig {
+    pub fn queue_file(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.queue_file {
+            return Some(expand_home(path));
+        }
+        let base = env::var_os("XDG_STATE_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+        Some(base.join("levari").join("scrobbles.jsonl"))
+    }
+}
+
+impl LayoutConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !(10..=90).contains(&self.shelf_width) {
+            return Err(format!("layout.shelf_width must be between 10 and 90, got {}", self.shelf_width).into());
+        }
+        if !(4..=40).contains(&self.player_height) {
+            return Err(format!("layout.player_height must be between 4 and 40, got {}", self.player_height).into());
+        }
+        Ok(())
+    }
+}
+
+impl NotificationsConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !(0.0..=3600.0).contains(&self.min_interval) {
+            let message = format!("notifications.min_interval must be between 0 and 3600, got {}", self.min_interval);
+            return Err(message.into());
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    // Reads the given file, or `levari/config.toml` under the XDG config
+    // directory. A missing default file just means defaults.
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let (path, explicit) = match path {
+            Some(p) => (p.to_path_buf(), true),
+            None => match default_path() {
+                Some(p) => (p, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+        };
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+}
+
+// Expands a leading `~/` so paths in the config can be written like in a shell.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("levari").join("config.toml"))
+}
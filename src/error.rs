@@ -0,0 +1,43 @@
+use std::{fmt, io, path::PathBuf};
+
+use rodio::{decoder::DecoderError, PlayError};
+
+// Things that can go wrong while putting an album on the platter. A single
+// bad track is skipped, only an album with nothing playable fails outright.
+#[derive(Debug)]
+pub enum PlayerError {
+    /// The audio device could not be opened
+    Output(PlayError),
+    Open(PathBuf, io::Error),
+    Decode(PathBuf, DecoderError),
+    /// Every track of the named album failed to open or decode
+    NothingPlayable(String),
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Output(e) => write!(f, "audio output: {}", e),
+            Self::Open(path, e) => write!(f, "cannot open {}: {}", path.display(), e),
+            Self::Decode(path, e) => write!(f, "cannot decode {}: {}", path.display(), e),
+            Self::NothingPlayable(album) => write!(f, "nothing playable on '{}'", album),
+        }
+    }
+}
+
+impl std::error::Error for PlayerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Output(e) => Some(e),
+            Self::Open(_, e) => Some(e),
+            Self::Decode(_, e) => Some(e),
+            Self::NothingPlayable(_) => None,
+        }
+    }
+}
+
+impl From<PlayError> for PlayerError {
+    fn from(e: PlayError) -> Self {
+        Self::Output(e)
+    }
+}
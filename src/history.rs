@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+// Position changes bigger than this between two updates are seeks and don't
+// count as heard. Ticks are a quarter second, a few of them may be missed.
+const MAX_STEP: f64 = 3.0;
+// A track counts as played to the end when it was left this close to it.
+const END_TOLERANCE: f64 = 2.0;
+
+// One line of the listening log, written when a track ends or is left.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Listen {
+    pub album: String,
+    pub album_path: PathBuf,
+    pub track: String,
+    pub track_path: PathBuf,
+    /// Counting from 1
+    pub track_number: usize,
+    /// Unix seconds
+    pub started: u64,
+    pub ended: u64,
+    /// Seconds of the track that were played, seeks don't count
+    pub heard: u64,
+    /// Seconds
+    pub duration: u64,
+    pub rpm: f32,
+    /// Played to the end, rather than skipped or stopped
+    pub finished: bool,
+}
+
+// Follows the playing track from tick to tick and hands out a `Listen`
+// each time one ends.
+#[derive(Default)]
+pub struct Tracker {
+    current: Option<(Listen, f64, f64)>,
+}
+
+impl Tracker {
+    // `playing` describes the playing track and where it is at, its times,
+    // `heard` and `finished` are filled in here. Nothing playing ends the
+    // current listen too.
+    pub fn update(&mut self, playing: Option<(Listen, f64)>) -> Option<Listen> {
+        let now = unix_now();
+        if let (Some((listen, position, heard)), Some((next, next_position))) = (&mut self.current, &playing) {
+            if listen.track_path == next.track_path && listen.album_path == next.album_path {
+                let step = next_position - *position;
+                if step > 0.0 && step <= MAX_STEP {
+                    *heard = (*heard + step).min(listen.duration as f64);
+                }
+                *position = *next_position;
+                listen.ended = now;
+                listen.rpm = next.rpm;
+                return None;
+            }
+        }
+        let ended = self.finish();
+        self.current = playing.map(|(mut listen, position)| {
+            listen.started = now;
+            listen.ended = now;
+            (listen, position, 0.0)
+        });
+        ended
+    }
+
+    // Ends the current listen, e.g. when levari quits.
+    pub fn finish(&mut self) -> Option<Listen> {
+        let (mut listen, position, heard) = self.current.take()?;
+        listen.heard = heard.round() as u64;
+        listen.finished = position + END_TOLERANCE >= listen.duration as f64;
+        Some(listen)
+    }
+}
+
+pub fn append(path: &Path, listen: &Listen) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(listen)?;
+    line.push('\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(line.as_bytes())
+}
+
+// Lines that don't parse, e.g. one cut short by a crash, are skipped.
+pub fn load(path: &Path) -> Result<Vec<Listen>, Box<dyn Error>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+    }
+}
+
+pub struct AlbumStats {
+    pub name: String,
+    pub path: PathBuf,
+    /// Seconds
+    pub heard: u64,
+    pub tracks: usize,
+}
+
+pub struct Stats {
+    pub listens: usize,
+    /// Seconds
+    pub heard: u64,
+    /// Unix seconds of the first listen
+    pub since: Option<u64>,
+    /// Most heard first
+    pub albums: Vec<AlbumStats>,
+    /// Seconds heard per weekday, Monday first, in local time
+    pub weekdays: [u64; 7],
+    /// Seconds heard per hour of the day, in local time
+    pub hours: [u64; 24],
+}
+
+impl Stats {
+    pub fn new(listens: &[Listen]) -> Self {
+        let mut albums: HashMap<&Path, AlbumStats> = HashMap::new();
+        let mut weekdays = [0; 7];
+        let mut hours = [0; 24];
+        for listen in listens {
+            let album = albums.entry(&listen.album_path).or_insert_with(|| AlbumStats {
+                name: listen.album.clone(),
+                path: listen.album_path.clone(),
+                heard: 0,
+                tracks: 0,
+            });
+            album.heard += listen.heard;
+            album.tracks += 1;
+            let (weekday, hour) = local_weekday_hour(listen.started);
+            weekdays[weekday] += listen.heard;
+            hours[hour] += listen.heard;
+        }
+        let mut albums: Vec<AlbumStats> = albums.into_values().collect();
+        albums.sort_by(|a, b| b.heard.cmp(&a.heard).then_with(|| a.name.cmp(&b.name)));
+        Self {
+            listens: listens.len(),
+            heard: listens.iter().map(|l| l.heard).sum(),
+            since: listens.iter().map(|l| l.started).min(),
+            albums,
+            weekdays,
+            hours,
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Weekday (Monday is 0) and hour of a Unix time in the local time zone.
+fn local_weekday_hour(unix: u64) -> (usize, usize) {
+    match local_time(unix) {
+        Some(tm) => (((tm.tm_wday + 6) % 7) as usize, tm.tm_hour as usize),
+        // 1970-01-01 was a Thursday.
+        None => (((unix / 86_400 + 3) % 7) as usize, (unix % 86_400 / 3600) as usize),
+    }
+}
+
+// "2024-05-01 21:07" in the local time zone, or "@<unix seconds>" if that
+// can't be worked out.
+pub fn format_local(unix: u64) -> String {
+    match local_time(unix) {
+        Some(tm) => format!(
+            "{}-{:02}-{:02} {:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min
+        ),
+        None => format!("@{}", unix),
+    }
+}
+
+fn local_time(unix: u64) -> Option<libc::tm> {
+    let time = unix as libc::time_t;
+    // SAFETY: `tm` is plain data that localtime_r fills in, and both
+    // pointers are valid for the duration of the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    (!unsafe { libc::localtime_r(&time, &mut tm) }.is_null()).then_some(tm)
+}
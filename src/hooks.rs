@@ -0,0 +1,123 @@
+use std::{
+    io,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use crate::{config::HooksConfig, metadata};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Insert,
+    Eject,
+    /// A track starts, including the first one of an inserted album
+    TrackChange,
+    Pause,
+    Resume,
+    Bookmark,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Eject => "eject",
+            Self::TrackChange => "track_change",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Bookmark => "bookmark",
+        }
+    }
+}
+
+// A hook to run: its event, command, environment and the track to read
+// the artist of.
+type Job = (Event, String, Vec<(&'static str, String)>, Option<PathBuf>);
+
+// Runs the configured commands with `sh -c` on a thread of their own, so a
+// slow script never holds up the player. They run one after another in the
+// order of the events, a quick pause and resume never ends up paused.
+// Failures are collected for the error log.
+pub struct Hooks {
+    config: HooksConfig,
+    /// Started with the first hook that runs
+    worker: Option<Sender<Job>>,
+    sender: Sender<String>,
+    failures: Receiver<String>,
+}
+
+impl Hooks {
+    pub fn new(config: &HooksConfig) -> Self {
+        let (sender, failures) = mpsc::channel();
+        Self { config: config.clone(), worker: None, sender, failures }
+    }
+
+    fn command(&self, event: Event) -> Option<&String> {
+        match event {
+            Event::Insert => self.config.on_insert.as_ref(),
+            Event::Eject => self.config.on_eject.as_ref(),
+            Event::TrackChange => self.config.on_track_change.as_ref(),
+            Event::Pause => self.config.on_pause.as_ref(),
+            Event::Resume => self.config.on_resume.as_ref(),
+            Event::Bookmark => self.config.on_bookmark.as_ref(),
+        }
+    }
+
+    // `env` is passed as is. With a `track`, its artist is read from the
+    // tags on the hook thread and added as LEVARI_ARTIST.
+    pub fn run(&mut self, event: Event, env: Vec<(&'static str, String)>, track: Option<PathBuf>) -> io::Result<()> {
+        let Some(command) = self.command(event).cloned() else {
+            return Ok(());
+        };
+        let worker = match self.worker {
+            Some(ref worker) => worker,
+            None => {
+                let (worker, jobs) = mpsc::channel();
+                let failures = self.sender.clone();
+                thread::Builder::new().name("hooks".into()).spawn(move || {
+                    for job in jobs {
+                        if let Some(failure) = run_job(job) {
+                            let _ = failures.send(failure);
+                        }
+                    }
+                })?;
+                self.worker.insert(worker)
+            }
+        };
+        // The thread only ends with the sender, which is still here.
+        let _ = worker.send((event, command, env, track));
+        Ok(())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = String> + '_ {
+        self.failures.try_iter()
+    }
+}
+
+fn run_job((event, command, mut env, track): Job) -> Option<String> {
+    env.push(("LEVARI_EVENT", event.name().to_string()));
+    if let Some(artist) = track.and_then(|t| metadata::probe(&t).ok()).and_then(|i| i.tags.artist) {
+        env.push(("LEVARI_ARTIST", artist));
+    }
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => None,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Some(match stderr.lines().find(|l| !l.trim().is_empty()) {
+                Some(line) => format!("Hook on_{} failed ({}): {}", event.name(), output.status, line.trim()),
+                None => format!("Hook on_{} failed ({})", event.name(), output.status),
+            })
+        }
+        Err(e) => Some(format!("Hook on_{}: {}", event.name(), e)),
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    error::Error,
+    fs,
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tiny_http::{Header, Method, Request as HttpRequest, Response, Server};
+
+use crate::{
+    ipc::{self, Command},
+    remote::Handle,
+};
+
+// The page is tiny and self-contained, so it is built into the binary.
+const INDEX: &str = include_str!("../assets/index.html");
+// Requests can wait on the main loop, so a few are served at once.
+const WORKERS: usize = 4;
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+// One album in `GET /api/shelf`. Covers are fetched by index.
+#[derive(Serialize)]
+struct ShelfEntry<'a> {
+    index: usize,
+    name: &'a str,
+    cover: bool,
+    tracks: usize,
+    /// Seconds
+    duration: u64,
+}
+
+// Serves the web page and the JSON API behind it:
+//
+// - `GET /api/status`: what is playing, as `levari ctl status` prints it
+// - `GET /api/shelf`: every album, in shelf order
+// - `GET /api/cover/<index>`: the cover image of an album
+// - `POST /api/insert` with `{"index": 3}` or `{"path": "..."}`
+// - `POST /api/eject`
+// - `POST /api/<command>` for the other socket commands, with the same
+//   fields, e.g. `/api/volume` with `{"percent": 40}`
+//
+// Answers are `{"ok": true}` or `{"ok": false, "error": "..."}` like on the
+// socket. Commands must be sent as `application/json`, which a web page
+// elsewhere can't do without asking first. Returns the address listened
+// on, which tells the port when `address` asked for any.
+pub fn spawn(address: &str, remote: Handle) -> Result<SocketAddr, Box<dyn Error>> {
+    let server = Arc::new(Server::http(address).map_err(|e| format!("{}: {}", address, e))?);
+    let bound = server.server_addr().to_ip().ok_or_else(|| format!("{}: not a TCP address", address))?;
+    let own_host = Arc::new(host_name(address).to_string());
+    for _ in 0..WORKERS {
+        let server = Arc::clone(&server);
+        let remote = remote.clone();
+        let own_host = Arc::clone(&own_host);
+        thread::Builder::new().name("http".into()).spawn(move || {
+            while let Ok(request) = server.recv() {
+                serve(request, &remote, &own_host);
+            }
+        })?;
+    }
+    Ok(bound)
+}
+
+fn serve(mut request: HttpRequest, remote: &Handle, own_host: &str) {
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    if let Err(e) = check_origin(&request, own_host) {
+        let _ = request.respond(failure(403, e));
+        return;
+    }
+    let response = match (request.method(), path.as_str()) {
+        (Method::Get, "/") => Response::from_string(INDEX).with_header(content_type("text/html; charset=utf-8")),
+        (Method::Get, "/api/status") => json(200, &remote.snapshot()),
+        (Method::Get, "/api/shelf") => {
+            let shelf = remote.shelf();
+            let entries: Vec<ShelfEntry> = shelf
+                .iter()
+                .map(|a| ShelfEntry {
+                    index: a.index,
+                    name: &a.name,
+                    cover: a.cover.is_some(),
+                    tracks: a.tracks.len(),
+                    duration: a.tracks.iter().map(|t| t.duration).sum(),
+                })
+                .collect();
+            json(200, &entries)
+        }
+        (Method::Get, cover) if cover.starts_with("/api/cover/") => {
+            let image = cover["/api/cover/".len()..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| remote.shelf().get(i)?.cover.clone())
+                .and_then(|path| Some((fs::read(&path).ok()?, image_type(&path))));
+            match image {
+                Some((bytes, kind)) => Response::from_data(bytes)
+                    .with_header(content_type(kind))
+                    .with_header(header("Cache-Control", "max-age=300")),
+                None => failure(404, "no cover".to_string()),
+            }
+        }
+        (Method::Post, action) if action.starts_with("/api/") => {
+            let name = action["/api/".len()..].to_string();
+            match read_body(&mut request).and_then(|body| command(&name, body, remote)) {
+                Ok(command) => match command.into_request().and_then(|r| remote.send(r)) {
+                    Ok(()) => json(200, &ipc::Response { ok: true, error: None, status: None }),
+                    Err(e) => failure(400, e),
+                },
+                Err(e) => failure(400, e),
+            }
+        }
+        (Method::Get | Method::Post, _) => failure(404, format!("no such page: {}", path)),
+        _ => failure(405, "only GET and POST are supported".to_string()),
+    };
+    let _ = request.respond(response);
+}
+
+// Guards against other web pages open in a browser on this machine or the
+// network: the Host must be this server under a name that can't be
+// rebound to it (an IP address, localhost or the configured one), and
+// anything coming from a page must come from this server's own.
+fn check_origin(request: &HttpRequest, own_host: &str) -> Result<(), String> {
+    let host = find_header(request, "Host").ok_or("missing Host header")?;
+    let name = host_name(host);
+    let known = name.eq_ignore_ascii_case("localhost") || name.eq_ignore_ascii_case(own_host);
+    if !known && name.parse::<IpAddr>().is_err() {
+        return Err(format!("unknown host {}", host));
+    }
+    if let Some(origin) = find_header(request, "Origin") {
+        if !origin.eq_ignore_ascii_case(&format!("http://{}", host)) {
+            return Err(format!("requests from {} are not allowed", origin));
+        }
+    }
+    Ok(())
+}
+
+// "127.0.0.1:8333" is "127.0.0.1", "[::1]:8333" is "::1".
+fn host_name(address: &str) -> &str {
+    match address.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => address.rsplit_once(':').map_or(address, |(name, _)| name),
+    }
+}
+
+fn find_header<'a>(request: &'a HttpRequest, name: &'static str) -> Option<&'a str> {
+    request.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
+}
+
+fn read_body(request: &mut HttpRequest) -> Result<Value, String> {
+    let json = find_header(request, "Content-Type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("application/json"));
+    if !json {
+        return Err("commands must be sent as Content-Type: application/json".to_string());
+    }
+    let mut body = String::new();
+    request.as_reader().take(MAX_BODY_BYTES).read_to_string(&mut body).map_err(|e| e.to_string())?;
+    if body.trim().is_empty() {
+        return Ok(Value::Object(Default::default()));
+    }
+    serde_json::from_str(&body).map_err(|e| format!("bad JSON: {}", e))
+}
+
+// The route names the command and the body carries its fields, so a
+// request maps onto the socket's `{"cmd": ...}` objects.
+fn command(name: &str, body: Value, remote: &Handle) -> Result<Command, String> {
+    let Value::Object(mut fields) = body else {
+        return Err("expected a JSON object".to_string());
+    };
+    let name = match name {
+        "eject" => "stop",
+        "insert" if fields.contains_key("index") => {
+            let index = fields.get("index").and_then(Value::as_u64).ok_or("index must be a number")?;
+            let shelf = remote.shelf();
+            let album = shelf.get(index as usize).ok_or_else(|| format!("no album at {}", index))?;
+            return Ok(Command::Insert { path: album.path.clone() });
+        }
+        "status" => return Err("use GET /api/status".to_string()),
+        name => name,
+    };
+    fields.insert("cmd".to_string(), Value::from(name));
+    serde_json::from_value(Value::Object(fields)).map_err(|e| format!("bad command: {}", e))
+}
+
+fn json(status: u16, value: &impl Serialize) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    Response::from_data(body).with_status_code(status).with_header(content_type("application/json"))
+}
+
+fn failure(status: u16, error: String) -> Response<std::io::Cursor<Vec<u8>>> {
+    json(status, &ipc::Response { ok: false, error: Some(error), status: None })
+}
+
+fn image_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase().as_str() {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => "image/jpeg",
+    }
+}
+
+fn content_type(value: &str) -> Header {
+    header("Content-Type", value)
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("header names and values are ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::mpsc::{self, Receiver},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::remote::{AlbumSnapshot, Remote};
+
+    // A server on a free port, with a stand-in main loop that accepts every
+    // request and passes it on to the test.
+    fn start() -> (String, Receiver<String>) {
+        let remote = Remote::new();
+        let album = |index: usize, name: &str| AlbumSnapshot {
+            index,
+            name: name.to_string(),
+            path: PathBuf::from(format!("/music/{}", name)),
+            ..Default::default()
+        };
+        remote.publish_shelf(vec![album(0, "First"), album(1, "Second")]);
+        let address = spawn("127.0.0.1:0", remote.handle()).unwrap();
+        let (seen, requests) = mpsc::channel();
+        thread::spawn(move || {
+            while let Some((request, reply)) = remote.recv_timeout(Duration::from_secs(10)) {
+                let _ = seen.send(format!("{:?}", request));
+                let _ = reply.send(Ok(()));
+            }
+        });
+        (format!("http://{}", address), requests)
+    }
+
+    // Status code and body, for errors as well.
+    fn answer(result: Result<ureq::Response, ureq::Error>) -> (u16, String) {
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("{}", e),
+        };
+        (response.status(), response.into_string().unwrap())
+    }
+
+    fn post(url: &str, body: &str) -> (u16, String) {
+        answer(ureq::post(url).set("Content-Type", "application/json").send_string(body))
+    }
+
+    #[test]
+    fn host_names() {
+        assert_eq!(host_name("127.0.0.1:8333"), "127.0.0.1");
+        assert_eq!(host_name("[::1]:8333"), "::1");
+        assert_eq!(host_name("localhost:8333"), "localhost");
+        assert_eq!(host_name("levari.lan"), "levari.lan");
+    }
+
+    #[test]
+    fn commands_become_requests() {
+        let (url, requests) = start();
+        let next = || requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(post(&format!("{}/api/volume", url), r#"{"percent": 40}"#), (200, r#"{"ok":true}"#.to_string()));
+        assert_eq!(next(), "Volume(0.4)");
+        assert_eq!(post(&format!("{}/api/eject", url), "").0, 200);
+        assert_eq!(next(), "Stop");
+        assert_eq!(post(&format!("{}/api/insert", url), r#"{"index": 1}"#).0, 200);
+        assert_eq!(next(), r#"Insert("/music/Second")"#);
+        assert_eq!(post(&format!("{}/api/skip", url), r#"{"track": 2}"#).0, 200);
+        assert_eq!(next(), "SkipTo(1)");
+
+        let (status, body) = post(&format!("{}/api/insert", url), r#"{"index": 7}"#);
+        assert_eq!((status, body.contains("no album at 7")), (400, true), "{}", body);
+        let (status, body) = post(&format!("{}/api/volume", url), r#"{"percent": "loud"}"#);
+        assert_eq!((status, body.contains("bad command")), (400, true), "{}", body);
+        assert_eq!(post(&format!("{}/api/status", url), "").0, 400);
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn commands_must_be_json() {
+        let (url, requests) = start();
+        let form = ureq::post(&format!("{}/api/pause", url)).set("Content-Type", "application/x-www-form-urlencoded");
+        let (status, body) = answer(form.send_string("x=1"));
+        assert_eq!((status, body.contains("application/json")), (400, true), "{}", body);
+        let (status, _) = answer(ureq::post(&format!("{}/api/pause", url)).send_string("{}"));
+        assert_eq!(status, 400);
+        assert!(requests.try_recv().is_err());
+
+        let typed = ureq::post(&format!("{}/api/pause", url)).set("Content-Type", "application/json; charset=utf-8");
+        assert_eq!(answer(typed.send_string("{}")).0, 200);
+    }
+
+    #[test]
+    fn foreign_hosts_and_origins_are_refused() {
+        let (url, requests) = start();
+        let status = format!("{}/api/status", url);
+        let port = url.rsplit(':').next().unwrap();
+        assert_eq!(answer(ureq::get(&status).call()).0, 200);
+        assert_eq!(answer(ureq::get(&status).set("Host", &format!("localhost:{}", port)).call()).0, 200);
+
+        let rebound = ureq::get(&status).set("Host", &format!("attacker.example:{}", port));
+        let (code, body) = answer(rebound.call());
+        assert_eq!((code, body.contains("unknown host")), (403, true), "{}", body);
+
+        let page = ureq::post(&format!("{}/api/pause", url))
+            .set("Content-Type", "application/json")
+            .set("Origin", "http://attacker.example");
+        let (code, body) = answer(page.send_string("{}"));
+        assert_eq!((code, body.contains("not allowed")), (403, true), "{}", body);
+        assert!(requests.try_recv().is_err());
+
+        let own = ureq::post(&format!("{}/api/pause", url)).set("Content-Type", "application/json").set("Origin", &url);
+        assert_eq!(answer(own.send_string("{}")).0, 200);
+    }
+}
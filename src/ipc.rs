@@ -0,0 +1,162 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::remote::{Handle, Request, Snapshot};
+
+// One JSON object per line in each direction, e.g.
+// `{"cmd":"volume","percent":40}` answered by `{"ok":true}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+    /// 1-based track number on the inserted album
+    Skip { track: usize },
+    /// Seconds into the album, or from the current position when relative
+    Seek {
+        seconds: f64,
+        #[serde(default)]
+        relative: bool,
+    },
+    Insert { path: PathBuf },
+    Volume { percent: u32 },
+    Rpm { rpm: u32 },
+    /// Toggles the bookmark of the album at `path`, or of the inserted one
+    Bookmark {
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+    Status,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Snapshot>,
+}
+
+impl Command {
+    // Status is answered from the snapshot and has no request.
+    pub fn into_request(self) -> Result<Request, String> {
+        Ok(match self {
+            Self::Play => Request::Play,
+            Self::Pause => Request::Pause,
+            Self::PlayPause => Request::PlayPause,
+            Self::Stop => Request::Stop,
+            Self::Next => Request::Next,
+            Self::Previous => Request::Previous,
+            Self::Skip { track: 0 } => return Err("tracks are numbered from 1".to_string()),
+            Self::Skip { track } => Request::SkipTo(track - 1),
+            Self::Seek { seconds, relative: true } => Request::Seek(seconds),
+            Self::Seek { seconds, relative: false } => Request::SeekAlbum(seconds.max(0.0)),
+            Self::Insert { path } => Request::Insert(path),
+            Self::Volume { percent } if percent <= 200 => Request::Volume(percent as f32 / 100.0),
+            Self::Volume { .. } => return Err("volume must be between 0 and 200".to_string()),
+            Self::Rpm { rpm: rpm @ (33 | 45 | 78) } => Request::Rpm(rpm as f32),
+            Self::Rpm { .. } => return Err("rpm must be 33, 45 or 78".to_string()),
+            Self::Bookmark { path } => Request::Bookmark(path),
+            Self::Status => return Err("status is a query, not an action".to_string()),
+        })
+    }
+}
+
+// `$XDG_RUNTIME_DIR/levari.sock`, or a per-user file in the temp directory.
+pub fn default_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("levari.sock"),
+        None => {
+            let user = env::var("USER").unwrap_or_else(|_| "user".to_string());
+            env::temp_dir().join(format!("levari-{}.sock", user))
+        }
+    }
+}
+
+// Removes the socket file when levari exits.
+pub struct Socket {
+    path: PathBuf,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Listens on `path` with a thread per client. A socket left behind by a
+// crashed instance is replaced, one that still answers is not.
+pub fn spawn(path: &Path, remote: Handle) -> Result<Socket, Box<dyn Error>> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("{} is in use, is levari already running?", path.display()).into());
+        }
+        fs::remove_file(path)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    thread::Builder::new().name("ipc".into()).spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let remote = remote.clone();
+            let _ = thread::Builder::new().name("ipc-client".into()).spawn(move || serve(stream, remote));
+        }
+    })?;
+    Ok(Socket { path: path.to_path_buf() })
+}
+
+fn serve(stream: UnixStream, remote: Handle) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Command>(&line) {
+            Ok(Command::Status) => Response { ok: true, error: None, status: Some(remote.snapshot()) },
+            Ok(command) => match command.into_request().and_then(|request| remote.send(request)) {
+                Ok(()) => Response { ok: true, error: None, status: None },
+                Err(e) => Response { ok: false, error: Some(e), status: None },
+            },
+            Err(e) => Response { ok: false, error: Some(format!("bad command: {}", e)), status: None },
+        };
+        let Ok(mut reply) = serde_json::to_string(&response) else {
+            return;
+        };
+        reply.push('\n');
+        if writer.write_all(reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+// The `levari ctl` side: sends one command and waits for the answer.
+pub fn send(path: &Path, command: &Command) -> Result<Response, Box<dyn Error>> {
+    let mut stream =
+        UnixStream::connect(path).map_err(|e| format!("cannot reach levari at {}: {}", path.display(), e))?;
+    let mut line = serde_json::to_string(command)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    Ok(serde_json::from_str(&answer)?)
+}
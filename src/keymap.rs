@@ -0,0 +1,591 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::Focus;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Quit,
+    Help,
+    ErrorLog,
+    Stats,
+    Details,
+    Lyrics,
+    LyricsDown,
+    LyricsUp,
+    Cancel,
+    CommandLine,
+    Down,
+    Up,
+    Left,
+    Right,
+    FocusDown,
+    FocusUp,
+    FocusLeft,
+    FocusRight,
+    Top,
+    Bottom,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    PlayPause,
+    Select,
+    VolumeUp,
+    VolumeDown,
+    SpeedUp,
+    SpeedDown,
+    Bookmark,
+    SetMark,
+    JumpToMark,
+    NextBookmark,
+    PrevBookmark,
+    JumpToPlaying,
+    Dig,
+    DigBookmarked,
+}
+
+impl Action {
+    // Actions that take the next typed letter as their argument, like vim marks.
+    pub fn takes_letter(self) -> bool {
+        matches!(self, Action::SetMark | Action::JumpToMark)
+    }
+}
+
+// A completely typed key sequence: the action, its count, and the letter for
+// actions that take one.
+#[derive(Clone, Copy, Debug)]
+pub struct Invocation {
+    pub action: Action,
+    pub count: Option<usize>,
+    pub letter: Option<char>,
+}
+
+pub struct ActionInfo {
+    pub action: Action,
+    pub name: &'static str,
+    pub keys: &'static [&'static str],
+    /// What the action does, per focus, `None` meaning everywhere
+    pub help: &'static [(Option<Focus>, &'static str)],
+}
+
+const EVERYWHERE: Option<Focus> = None;
+const PLAYER: Option<Focus> = Some(Focus::Vinyl);
+const SHELF: Option<Focus> = Some(Focus::Albums);
+const BACKSIDE: Option<Focus> = Some(Focus::SongList);
+
+// Every bindable action with its config name, default keys and help text.
+// Input handling and the help overlay are both driven by this table.
+pub const ACTIONS: &[ActionInfo] = &[
+    ActionInfo { action: Action::Quit, name: "quit", keys: &["q"], help: &[(EVERYWHERE, "Quit")] },
+    ActionInfo { action: Action::Help, name: "help", keys: &["?"], help: &[(EVERYWHERE, "Show this help")] },
+    ActionInfo {
+        action: Action::Details,
+        name: "details",
+        keys: &["i"],
+        help: &[(EVERYWHERE, "Details, tags and liner notes of the selected album")],
+    },
+    ActionInfo { action: Action::Lyrics, name: "lyrics", keys: &["y"], help: &[(EVERYWHERE, "Show/hide lyrics")] },
+    ActionInfo {
+        action: Action::LyricsDown,
+        name: "lyrics_down",
+        keys: &["<C-e>"],
+        help: &[(EVERYWHERE, "Scroll unsynced lyrics down")],
+    },
+    ActionInfo {
+        action: Action::LyricsUp,
+        name: "lyrics_up",
+        keys: &["<C-y>"],
+        help: &[(EVERYWHERE, "Scroll unsynced lyrics up")],
+    },
+    ActionInfo {
+        action: Action::ErrorLog,
+        name: "error_log",
+        keys: &["e"],
+        help: &[(EVERYWHERE, "Show errors, e.g. tracks that could not be played")],
+    },
+    ActionInfo {
+        action: Action::Stats,
+        name: "stats",
+        keys: &["s"],
+        help: &[(EVERYWHERE, "Listening statistics from the history file")],
+    },
+    ActionInfo {
+        action: Action::CommandLine,
+        name: "command_line",
+        keys: &[":"],
+        help: &[(EVERYWHERE, "Command line, e.g. :volume 40, :seek 2:30, :insert <name>")],
+    },
+    ActionInfo {
+        action: Action::Cancel,
+        name: "cancel",
+        keys: &["<Esc>"],
+        help: &[(EVERYWHERE, "Close popups, clear pending keys")],
+    },
+    ActionInfo {
+        action: Action::Down,
+        name: "down",
+        keys: &["j", "<Down>"],
+        help: &[(PLAYER, "Focus the shelf"), (SHELF, "Next album"), (BACKSIDE, "Next song")],
+    },
+    ActionInfo {
+        action: Action::Up,
+        name: "up",
+        keys: &["k", "<Up>"],
+        help: &[(SHELF, "Previous album, or the player from the top"), (BACKSIDE, "Previous song")],
+    },
+    ActionInfo {
+        action: Action::Left,
+        name: "left",
+        keys: &["h", "<Left>"],
+        help: &[(PLAYER, "Eject the album"), (BACKSIDE, "Back to the shelf")],
+    },
+    ActionInfo { action: Action::Right, name: "right", keys: &["l", "<Right>"], help: &[(SHELF, "Open the backside")] },
+    ActionInfo { action: Action::FocusDown, name: "focus_down", keys: &["J"], help: &[(PLAYER, "Focus the shelf")] },
+    ActionInfo {
+        action: Action::FocusUp,
+        name: "focus_up",
+        keys: &["K"],
+        help: &[(SHELF, "Focus the player"), (BACKSIDE, "Focus the player")],
+    },
+    ActionInfo { action: Action::FocusLeft, name: "focus_left", keys: &["H"], help: &[(BACKSIDE, "Focus the shelf")] },
+    ActionInfo {
+        action: Action::FocusRight,
+        name: "focus_right",
+        keys: &["L"],
+        help: &[(SHELF, "Focus the backside of the inserted album")],
+    },
+    ActionInfo { action: Action::Top, name: "top", keys: &["gg"], help: &[(SHELF, "First album, or album N")] },
+    ActionInfo { action: Action::Bottom, name: "bottom", keys: &["G"], help: &[(SHELF, "Last album, or album N")] },
+    ActionInfo {
+        action: Action::HalfPageDown,
+        name: "half_page_down",
+        keys: &["<C-d>"],
+        help: &[(SHELF, "Half a page down"), (BACKSIDE, "Half a page down")],
+    },
+    ActionInfo {
+        action: Action::HalfPageUp,
+        name: "half_page_up",
+        keys: &["<C-u>"],
+        help: &[(SHELF, "Half a page up"), (BACKSIDE, "Half a page up")],
+    },
+    ActionInfo {
+        action: Action::PageDown,
+        name: "page_down",
+        keys: &["<C-f>", "<PageDown>"],
+        help: &[(SHELF, "A page down"), (BACKSIDE, "A page down")],
+    },
+    ActionInfo {
+        action: Action::PageUp,
+        name: "page_up",
+        keys: &["<C-b>", "<PageUp>"],
+        help: &[(SHELF, "A page up"), (BACKSIDE, "A page up")],
+    },
+    ActionInfo {
+        action: Action::PlayPause,
+        name: "play_pause",
+        keys: &["<Space>"],
+        help: &[(EVERYWHERE, "Play/pause")],
+    },
+    ActionInfo {
+        action: Action::Select,
+        name: "select",
+        keys: &["<Enter>"],
+        help: &[
+            (PLAYER, "Insert/eject the selected album"),
+            (SHELF, "Insert/eject the selected album"),
+            (BACKSIDE, "Skip to the song"),
+        ],
+    },
+    ActionInfo { action: Action::VolumeUp, name: "volume_up", keys: &["+", "="], help: &[(EVERYWHERE, "Louder")] },
+    ActionInfo { action: Action::VolumeDown, name: "volume_down", keys: &["-"], help: &[(EVERYWHERE, "Quieter")] },
+    ActionInfo { action: Action::SpeedUp, name: "speed_up", keys: &[">"], help: &[(EVERYWHERE, "Faster RPM")] },
+    ActionInfo { action: Action::SpeedDown, name: "speed_down", keys: &["<lt>"], help: &[(EVERYWHERE, "Slower RPM")] },
+    ActionInfo { action: Action::Bookmark, name: "bookmark", keys: &["b"], help: &[(SHELF, "Toggle bookmark")] },
+    ActionInfo {
+        action: Action::SetMark,
+        name: "set_mark",
+        keys: &["m"],
+        help: &[(SHELF, "Mark the album with the next letter, e.g. ma")],
+    },
+    ActionInfo {
+        action: Action::JumpToMark,
+        name: "jump_to_mark",
+        keys: &["'"],
+        help: &[(EVERYWHERE, "Jump to the album marked with the next letter, e.g. 'a")],
+    },
+    ActionInfo { action: Action::NextBookmark, name: "next_bookmark", keys: &["n"], help: &[(SHELF, "Next bookmark")] },
+    ActionInfo {
+        action: Action::PrevBookmark,
+        name: "prev_bookmark",
+        keys: &["N", "<C-n>"],
+        help: &[(SHELF, "Previous bookmark")],
+    },
+    ActionInfo {
+        action: Action::JumpToPlaying,
+        name: "jump_to_playing",
+        keys: &["p"],
+        help: &[(EVERYWHERE, "Jump to the playing album")],
+    },
+    ActionInfo { action: Action::Dig, name: "dig", keys: &["d"], help: &[(EVERYWHERE, "Dig the crate")] },
+    ActionInfo {
+        action: Action::DigBookmarked,
+        name: "dig_bookmarked",
+        keys: &["D"],
+        help: &[(EVERYWHERE, "Dig the crate, favouring bookmarks")],
+    },
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn new(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        let code = match code {
+            // Shift is already part of the character (and of BackTab)
+            KeyCode::Char(c) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                if modifiers.contains(KeyModifiers::CONTROL) {
+                    KeyCode::Char(c.to_ascii_lowercase())
+                } else {
+                    KeyCode::Char(c)
+                }
+            }
+            KeyCode::BackTab => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            code => code,
+        };
+        Self { code, modifiers: modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT) }
+    }
+}
+
+impl Key {
+    pub fn letter(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.is_empty() && c.is_ascii_lowercase() => Some(c),
+            _ => None,
+        }
+    }
+
+    // Plain digit keys, used for count prefixes.
+    pub fn digit(&self) -> Option<usize> {
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.is_empty() => c.to_digit(10).map(|d| d as usize),
+            _ => None,
+        }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Enter => "Enter".to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::BackTab => "S-Tab".to_string(),
+            KeyCode::Backspace => "BS".to_string(),
+            KeyCode::Delete => "Del".to_string(),
+            KeyCode::Insert => "Insert".to_string(),
+            KeyCode::Up => "Up".to_string(),
+            KeyCode::Down => "Down".to_string(),
+            KeyCode::Left => "Left".to_string(),
+            KeyCode::Right => "Right".to_string(),
+            KeyCode::Home => "Home".to_string(),
+            KeyCode::End => "End".to_string(),
+            KeyCode::PageUp => "PageUp".to_string(),
+            KeyCode::PageDown => "PageDown".to_string(),
+            KeyCode::F(n) => format!("F{}", n),
+            other => format!("{:?}", other),
+        };
+        let mut prefix = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            prefix.push_str("C-");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            prefix.push_str("A-");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            prefix.push_str("S-");
+        }
+        if prefix.is_empty() && name.chars().count() == 1 {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "<{}{}>", prefix, name)
+        }
+    }
+}
+
+fn parse_special(spec: &str) -> Result<Key, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = spec;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        if rest.len() > 2 && lower.starts_with("c-") {
+            modifiers |= KeyModifiers::CONTROL;
+        } else if rest.len() > 2 && (lower.starts_with("a-") || lower.starts_with("m-")) {
+            modifiers |= KeyModifiers::ALT;
+        } else if rest.len() > 2 && lower.starts_with("s-") {
+            modifiers |= KeyModifiers::SHIFT;
+        } else {
+            break;
+        }
+        rest = &rest[2..];
+    }
+    let code = match rest.to_ascii_lowercase().as_str() {
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "gt" => KeyCode::Char('>'),
+        "enter" | "cr" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        f if f.len() > 1 && f.starts_with('f') => {
+            KeyCode::F(f[1..].parse().map_err(|_| format!("unknown key '<{}>'", spec))?)
+        }
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                // Terminals send the shifted character rather than Shift,
+                // so `<S-x>` is `X`.
+                (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+                    if !c.is_alphabetic() {
+                        return Err(format!("'<{}>' would be read as '{}', bind the shifted character itself", spec, c));
+                    }
+                    KeyCode::Char(c.to_uppercase().next().unwrap_or(c))
+                }
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(format!("unknown key '<{}>'", spec)),
+            }
+        }
+    };
+    Ok(Key::new(code, modifiers))
+}
+
+// Parses vim-style key notation, e.g. "gg", "<C-d>" or "<Space>", into a key sequence.
+pub fn parse_keys(notation: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest[1..].find('>') {
+                let spec = &rest[1..=end];
+                if !spec.is_empty() {
+                    let key = parse_special(spec).map_err(|e| format!("{} in '{}'", e, notation))?;
+                    keys.push(key);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::new(KeyCode::Char(c), KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return Err("empty key binding".to_string());
+    }
+    Ok(keys)
+}
+
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter().map(|k| k.to_string()).collect()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    fn notations(&self) -> Vec<&str> {
+        match self {
+            KeyList::One(s) => vec![s.as_str()],
+            KeyList::Many(v) => v.iter().map(|s| s.as_str()).collect(),
+        }
+    }
+}
+
+pub enum Lookup {
+    Action(Action),
+    Pending,
+    None,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Keymap {
+    // Builds the keymap from the defaults, replacing the keys of any action
+    // named in `overrides`, and rejects ambiguous bindings.
+    pub fn new(overrides: &HashMap<String, KeyList>) -> Result<Self, Box<dyn Error>> {
+        for name in overrides.keys() {
+            if !ACTIONS.iter().any(|a| a.name == name) {
+                let known: Vec<&str> = ACTIONS.iter().map(|a| a.name).collect();
+                let msg = format!("unknown action '{}' in [keys], expected one of: {}", name, known.join(", "));
+                return Err(msg.into());
+            }
+        }
+        let mut bindings: Vec<(Vec<Key>, Action)> = Vec::new();
+        for info in ACTIONS {
+            let notations = match overrides.get(info.name) {
+                Some(list) => list.notations(),
+                None => info.keys.to_vec(),
+            };
+            for notation in notations {
+                let keys = parse_keys(notation).map_err(|e| format!("keys.{}: {}", info.name, e))?;
+                bindings.push((keys, info.action));
+            }
+        }
+
+        for (i, (a_keys, a_action)) in bindings.iter().enumerate() {
+            for (b_keys, b_action) in bindings.iter().skip(i + 1) {
+                if a_keys == b_keys && a_action != b_action {
+                    return Err(format!(
+                        "'{}' is bound to both '{}' and '{}'",
+                        format_keys(a_keys),
+                        action_name(*a_action),
+                        action_name(*b_action)
+                    )
+                    .into());
+                }
+                let (short, long) = if a_keys.len() < b_keys.len() {
+                    ((a_keys, a_action), (b_keys, b_action))
+                } else {
+                    ((b_keys, b_action), (a_keys, a_action))
+                };
+                if short.0.len() < long.0.len() && long.0.starts_with(short.0) {
+                    return Err(format!(
+                        "'{}' ({}) is a prefix of '{}' ({}), so the latter could never be typed",
+                        format_keys(short.0),
+                        action_name(*short.1),
+                        format_keys(long.0),
+                        action_name(*long.1)
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(Self { bindings })
+    }
+
+    // The keys currently bound to an action, in notation.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        self.bindings.iter().filter(|(_, a)| *a == action).map(|(keys, _)| format_keys(keys)).collect()
+    }
+
+    pub fn lookup(&self, pending: &[Key]) -> Lookup {
+        let mut prefix = false;
+        for (keys, action) in &self.bindings {
+            if keys.as_slice() == pending {
+                return Lookup::Action(*action);
+            }
+            if keys.starts_with(pending) {
+                prefix = true;
+            }
+        }
+        if prefix {
+            Lookup::Pending
+        } else {
+            Lookup::None
+        }
+    }
+}
+
+pub fn action_name(action: Action) -> &'static str {
+    ACTIONS.iter().find(|a| a.action == action).map(|a| a.name).unwrap_or("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char, modifiers: KeyModifiers) -> Key {
+        Key::new(KeyCode::Char(c), modifiers)
+    }
+
+    fn keymap(overrides: &[(&str, &str)]) -> Result<Keymap, String> {
+        let overrides = overrides.iter().map(|(a, k)| (a.to_string(), KeyList::One(k.to_string()))).collect();
+        Keymap::new(&overrides).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn notation() {
+        assert_eq!(parse_keys("<C-n>"), Ok(vec![key('n', KeyModifiers::CONTROL)]));
+        assert_eq!(parse_keys("<c-N>"), parse_keys("<C-n>"));
+        assert_eq!(parse_keys("<S-x>"), Ok(vec![key('X', KeyModifiers::NONE)]));
+        assert_eq!(parse_keys("<S-x>"), parse_keys("X"));
+        assert_eq!(parse_keys("<S-Tab>"), Ok(vec![Key::new(KeyCode::BackTab, KeyModifiers::NONE)]));
+        assert_eq!(
+            parse_keys("<S-1>"),
+            Err("'<S-1>' would be read as '1', bind the shifted character itself in '<S-1>'".to_string())
+        );
+        assert_eq!(parse_keys("gg"), Ok(vec![key('g', KeyModifiers::NONE); 2]));
+        assert_eq!(parse_keys("<lt><Space>"), Ok(vec![key('<', KeyModifiers::NONE), key(' ', KeyModifiers::NONE)]));
+        assert_eq!(parse_keys("<>"), Ok(vec![key('<', KeyModifiers::NONE), key('>', KeyModifiers::NONE)]));
+        assert_eq!(parse_keys("<C-Nope>"), Err("unknown key '<C-Nope>' in '<C-Nope>'".to_string()));
+        assert_eq!(parse_keys(""), Err("empty key binding".to_string()));
+    }
+
+    #[test]
+    fn sequences_wait_for_their_last_key() {
+        let keymap = keymap(&[]).unwrap();
+        let g = key('g', KeyModifiers::NONE);
+        assert!(matches!(keymap.lookup(&[g]), Lookup::Pending));
+        assert!(matches!(keymap.lookup(&[g, g]), Lookup::Action(Action::Top)));
+        assert!(matches!(keymap.lookup(&[g, key('x', KeyModifiers::NONE)]), Lookup::None));
+        assert!(matches!(keymap.lookup(&[key('n', KeyModifiers::CONTROL)]), Lookup::Action(Action::PrevBookmark)));
+    }
+
+    #[test]
+    fn overrides_replace_the_defaults() {
+        let keymap = keymap(&[("top", "<Home>"), ("quit", "<C-q>")]).unwrap();
+        assert_eq!(keymap.keys_for(Action::Top), ["<Home>"]);
+        assert_eq!(keymap.keys_for(Action::Quit), ["<C-q>"]);
+        assert!(matches!(keymap.lookup(&[key('g', KeyModifiers::NONE)]), Lookup::None));
+    }
+
+    #[test]
+    fn ambiguous_bindings_are_rejected() {
+        assert_eq!(keymap(&[("quit", "j")]).err().unwrap(), "'j' is bound to both 'quit' and 'down'");
+        // `<S-j>` is `J`, which is taken.
+        assert_eq!(keymap(&[("quit", "<S-j>")]).err().unwrap(), "'J' is bound to both 'quit' and 'focus_down'");
+        assert_eq!(
+            keymap(&[("bottom", "g")]).err().unwrap(),
+            "'g' (bottom) is a prefix of 'gg' (top), so the latter could never be typed"
+        );
+        assert_eq!(
+            keymap(&[("quit", "<S-1>")]).err().unwrap(),
+            "keys.quit: '<S-1>' would be read as '1', bind the shifted character itself in '<S-1>'"
+        );
+        assert!(keymap(&[("teleport", "t")]).err().unwrap().starts_with("unknown action 'teleport' in [keys]"));
+    }
+}
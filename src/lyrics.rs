@@ -0,0 +1,130 @@
+use std::{fs, path::Path};
+
+use crate::metadata;
+
+// Lyrics of one track. Lines of synced lyrics carry the second they start
+// at, unsynced lyrics have no times at all.
+pub struct Lyrics {
+    pub lines: Vec<(Option<f64>, String)>,
+}
+
+impl Lyrics {
+    // A sibling `.lrc` file wins over lyrics embedded in the tags (ID3 USLT
+    // or a Vorbis LYRICS comment), which may be in LRC format themselves.
+    pub fn load(song: &Path) -> Option<Self> {
+        let text = fs::read_to_string(song.with_extension("lrc"))
+            .ok()
+            .or_else(|| metadata::probe(song).ok()?.tags.lyrics)?;
+        let lyrics = parse(&text);
+        (!lyrics.lines.is_empty()).then_some(lyrics)
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.lines.first().is_some_and(|(time, _)| time.is_some())
+    }
+
+    // Index of the line being sung at `position` seconds into the track.
+    pub fn current_line(&self, position: f64) -> Option<usize> {
+        self.lines.iter().rposition(|(time, _)| time.is_some_and(|t| t <= position))
+    }
+}
+
+// ID tags of the LRC format, dropped from the text.
+const ID_TAGS: &[&str] = &["ar", "al", "ti", "au", "by", "length", "re", "tool", "ve", "#"];
+
+// Reads LRC: `[mm:ss.xx]` stamps in front of a line, several stamps for a
+// repeated line, and an `[offset:ms]` tag. ID tags like `[ar:...]` are
+// dropped. Anything else, section headers like `[Chorus]` too, is kept as
+// plain, unsynced lines.
+pub fn parse(text: &str) -> Lyrics {
+    let mut offset = 0.0;
+    let mut synced = Vec::new();
+    let mut plain = Vec::new();
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        let mut tagged = false;
+        while let Some(end) = rest.strip_prefix('[').and_then(|r| r.find(']')) {
+            let tag = &rest[1..end + 1];
+            if let Some(ms) = tag.strip_prefix("offset:") {
+                // A positive offset makes the lyrics come sooner.
+                offset = ms.trim().parse::<f64>().unwrap_or(0.0) / 1000.0;
+            } else if let Some(time) = parse_stamp(tag) {
+                times.push(time);
+            } else if !tag.split_once(':').is_some_and(|(id, _)| ID_TAGS.contains(&id.trim())) {
+                break;
+            }
+            rest = rest[end + 2..].trim_start();
+            tagged = true;
+        }
+        for time in &times {
+            synced.push((Some(*time), rest.to_string()));
+        }
+        if !tagged {
+            plain.push((None, raw.trim_end().to_string()));
+        }
+    }
+    if synced.is_empty() {
+        // Trim blank lines around the text, keep the ones between verses.
+        while plain.last().is_some_and(|(_, l): &(_, String)| l.is_empty()) {
+            plain.pop();
+        }
+        let start = plain.iter().position(|(_, l)| !l.is_empty()).unwrap_or(plain.len());
+        return Lyrics { lines: plain.split_off(start) };
+    }
+    for (time, _) in &mut synced {
+        *time = time.map(|t| (t - offset).max(0.0));
+    }
+    synced.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    Lyrics { lines: synced }
+}
+
+// "mm:ss", "mm:ss.xx" or "mm:ss:xx", as different tools write them.
+fn parse_stamp(tag: &str) -> Option<f64> {
+    let (min, sec) = tag.split_once(':')?;
+    let min: f64 = min.trim().parse().ok()?;
+    let sec: f64 = match sec.split_once(':') {
+        Some((s, frac)) => format!("{}.{}", s, frac).parse().ok()?,
+        None => sec.trim().parse().ok()?,
+    };
+    Some(min * 60.0 + sec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<(Option<f64>, String)> {
+        parse(text).lines
+    }
+
+    #[test]
+    fn section_headers_stay_in_unsynced_lyrics() {
+        let text = "[ar:Someone]\n[Verse 1]\nFirst line\n\n[Chorus] Sing it\n";
+        let expected = ["[Verse 1]", "First line", "", "[Chorus] Sing it"];
+        assert_eq!(lines(text), expected.map(|l| (None, l.to_string())));
+    }
+
+    #[test]
+    fn repeated_lines_are_sorted_by_time() {
+        let text = "[ti:Song]\n[00:01.00][00:10.50]Again\n[00:05.00]Between\n[00:12.00][Chorus] Text";
+        assert_eq!(
+            lines(text),
+            vec![
+                (Some(1.0), "Again".to_string()),
+                (Some(5.0), "Between".to_string()),
+                (Some(10.5), "Again".to_string()),
+                (Some(12.0), "[Chorus] Text".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn offset_moves_lines_sooner() {
+        let text = "[offset:500]\n[00:02.00]One\n[00:00.20]Zero\n[01:03:50]Later";
+        assert_eq!(
+            lines(text),
+            vec![(Some(0.0), "Zero".to_string()), (Some(1.5), "One".to_string()), (Some(63.0), "Later".to_string())]
+        );
+    }
+}
@@ -1,50 +1,183 @@
+mod command;
+mod config;
+mod error;
+mod history;
+mod hooks;
+mod http;
+mod ipc;
+mod keymap;
+mod lyrics;
+mod metadata;
+mod mpd;
+mod mpris;
+mod notify;
+mod nowplaying;
+mod remote;
+mod scrobble;
+mod state;
+mod theme;
+
 use std::{
     cmp::Ordering,
+    collections::{HashMap, HashSet},
     error::Error,
+    fmt::Display,
     fs,
     fs::File,
     io::{self, BufReader},
+    panic,
     path::{Path, PathBuf},
-    time::{Duration, Instant},
+    sync::{
+        atomic::{self, AtomicBool},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use command::{Command, Seek};
+use config::{Config, LayoutConfig};
+use error::PlayerError;
+use history::{Listen, Stats};
+use hooks::{Event, Hooks};
+use notify::{Notification, Notifier};
+use nowplaying::NowPlaying;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event as CEvent, KeyCode, KeyModifiers},
-    execute,
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event as CEvent, KeyCode, KeyEvent, KeyModifiers, MouseButton,
+        MouseEvent, MouseEventKind,
+    },
+    cursor, execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use rand::prelude::*;
+use rand::{distr::weighted::WeightedIndex, prelude::*, rngs::StdRng};
+use keymap::{Action, Invocation, Key, Keymap, Lookup, ACTIONS};
+use lyrics::Lyrics;
+use metadata::AlbumDetails;
+use scrobble::Scrobbler;
+use remote::{AlbumSnapshot, Remote, Reply, Request, Snapshot, Status, TrackSnapshot};
 use regex::Regex;
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use state::State;
+use theme::Theme;
+use unicode_width::UnicodeWidthChar;
 use tui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
+    style::{Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, LineGauge, List, ListItem, ListState, Paragraph},
     Terminal,
 };
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum Focus {
     Vinyl,
     Albums,
     SongList,
 }
 
+// Older entries are dropped from the error log past this.
+const MAX_LOG_ENTRIES: usize = 200;
+const TICK_RATE: Duration = Duration::from_millis(250);
+
+// Popups drawn over the whole interface, one at a time.
+#[derive(PartialEq, Clone, Copy)]
+enum Overlay {
+    Help,
+    Log,
+    Details,
+    Stats,
+}
+
 #[derive(PartialEq)]
 enum AppState {
     Browsing,
     Playing,
-    SongList,
 }
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(author, version, about, subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short = 'd', long = "datadir")]
-    datadir: PathBuf,
+    #[arg(short = 'd', long = "datadir", required = true)]
+    datadir: Option<PathBuf>,
+    /// Config file, defaults to $XDG_CONFIG_HOME/levari/config.toml
+    #[arg(short = 'c', long = "config")]
+    config: Option<PathBuf>,
+    /// Seed for the shelf shuffle, to recover an arrangement
+    #[arg(short = 's', long = "seed", conflicts_with = "daily")]
+    seed: Option<u64>,
+    /// Derive the seed from today's date (UTC), so everyone gets the same shelf
+    #[arg(long = "daily")]
+    daily: bool,
+    /// Leave the mouse to the terminal, e.g. for text selection
+    #[arg(long = "no-mouse")]
+    no_mouse: bool,
+    /// Run without the interface, controlled only through the socket, MPRIS, MPD or HTTP
+    #[arg(long = "headless", conflicts_with = "no_mouse")]
+    headless: bool,
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Control a running levari over its socket
+    Ctl {
+        /// Defaults to the socket from the config, or $XDG_RUNTIME_DIR/levari.sock
+        #[arg(long = "socket", global = true)]
+        socket: Option<PathBuf>,
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Look at or submit the queue of scrobbles
+    Scrobble {
+        #[command(subcommand)]
+        command: ScrobbleCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ScrobbleCommand {
+    /// List the scrobbles waiting to be submitted
+    Queue,
+    /// Submit the queue now
+    Submit,
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Resume, or insert the selected album if none is
+    Play,
+    Pause,
+    /// Play or pause
+    Toggle,
+    /// Eject the album
+    Stop,
+    /// Skip to the next track
+    Next,
+    /// Restart the track, or go to the previous one near its start
+    Previous,
+    /// Jump to a track of the inserted album, counting from 1
+    Skip { track: usize },
+    /// Go to a time on the album, or move by one with a leading + or -
+    Seek {
+        #[arg(allow_hyphen_values = true)]
+        time: String,
+    },
+    /// Insert the album in this directory
+    Insert { path: PathBuf },
+    /// Volume in percent, 0-200
+    Volume { percent: u32 },
+    /// 33, 45 or 78
+    Rpm { rpm: u32 },
+    /// Toggle the bookmark of an album, the inserted one by default
+    Bookmark { path: Option<PathBuf> },
+    /// Print what is playing as JSON
+    Status,
 }
 
 #[derive(Debug)]
@@ -52,6 +185,8 @@ struct Song {
     title: String,
     duration: u64,
     path: PathBuf,
+    /// Failed to open or decode, skipped from then on
+    unplayable: bool,
 }
 
 #[derive(Debug)]
@@ -61,6 +196,19 @@ struct Album {
     cover: Option<PathBuf>,
     songs: Vec<Song>,
     bookmarked: bool,
+    /// Seeded from the history file at startup, if there is one
+    last_played: Option<SystemTime>,
+}
+
+// A sink freshly loaded by `create_album_sink`.
+struct Loaded {
+    sink: Sink,
+    /// Album indexes of the queued tracks, in play order
+    tracks: Vec<usize>,
+    /// Tracks left out because they could not be played
+    skipped: usize,
+    /// Seconds skipped at the start of the first queued track
+    offset: f64,
 }
 
 struct App {
@@ -68,25 +216,98 @@ struct App {
     state: AppState,
     selected_index: usize,
     playing_album: Option<usize>,
-    playback_start: Option<Instant>,
-    pause_duration: Duration,
     paused: bool,
-    pause_start: Option<Instant>,
     album_list_state: ListState,
     song_list_state: ListState,
     current_sink: Option<Sink>,
+    /// What `current_sink` holds, see `Loaded`
+    queue: Vec<usize>,
+    queue_offset: f64,
+    /// Set by a remote `Quit`, the main loop exits when it sees it
+    quit_requested: bool,
+    /// The albums changed since the shelf was last published to remotes
+    shelf_changed: bool,
+    /// Last track seen playing, so the backside cursor can follow it
+    followed_track: Option<usize>,
+    hooks: Hooks,
+    /// Last track the track change hook ran for
+    announced_track: Option<PathBuf>,
+    now_playing_files: Option<nowplaying::Writer>,
+    /// Tagged artist of the playing track, read when it starts
+    artist: Option<(PathBuf, Option<String>)>,
+    history_file: Option<PathBuf>,
+    listening: history::Tracker,
+    /// Set when scrobbling is on and its submitter could be started
+    scrobbler: Option<Scrobbler>,
+    /// Set when desktop notifications are on and the bus could be reached
+    notifier: Option<Notifier>,
+    /// Read from the history file when the statistics are opened
+    stats: Option<Result<Stats, String>>,
     current_message: Option<String>,
     volume: f32,
-    current_song_index: usize,
     focus: Focus,
     title_phrase: String,
     playback_speed: f32,
-    pending_g: bool,
+    keymap: Keymap,
+    pending_keys: Vec<Key>,
+    pending_count: Option<usize>,
+    awaiting_letter: Option<Action>,
+    marks: HashMap<char, PathBuf>,
+    state_file: Option<PathBuf>,
+    datadir: PathBuf,
+    command_line: Option<String>,
+    command_history: Vec<String>,
+    history_pos: Option<usize>,
+    completions: Vec<String>,
+    completion_pos: usize,
+    theme: Theme,
+    layout: LayoutConfig,
+    overlay: Option<Overlay>,
+    overlay_scroll: u16,
+    error_log: Vec<(Instant, String)>,
+    /// Also print errors to stderr, where they are seen when headless
+    echo_errors: bool,
+    /// Detail view of the album at this path, read when first opened
+    details: Option<(PathBuf, AlbumDetails)>,
+    show_lyrics: bool,
+    /// Lyrics of the track at this path, `None` inside if it has none
+    lyrics: Option<(PathBuf, Option<Lyrics>)>,
+    /// Manual scroll, only used for unsynced lyrics
+    lyrics_scroll: u16,
     message_time: Option<Instant>,
+    seed: u64,
+    rng: StdRng,
+    // Screen areas from the last draw, used to hit-test mouse events
+    shelf_area: Rect,
+    shelf_offset: usize,
+    backside_area: Rect,
+    backside_offset: usize,
+    progress_area: Rect,
+    lyrics_area: Rect,
+    last_click: Option<(Instant, usize)>,
+    seek_preview: Option<f64>,
 }
 
 impl App {
-    fn new(albums: Vec<Album>) -> Self {
+    fn new(
+        albums: Vec<Album>,
+        datadir: &Path,
+        config: &Config,
+        seed: u64,
+        mut rng: StdRng,
+    ) -> Result<Self, Box<dyn Error>> {
+        config.layout.validate()?;
+        config.notifications.validate()?;
+        let state_file = config.state_file.as_deref().map(config::expand_home);
+        let state = match state_file {
+            Some(ref path) => State::load(path)?,
+            None => State::default(),
+        };
+        let marks = state
+            .marks
+            .into_iter()
+            .filter_map(|(letter, path)| Some((letter.chars().next()?, path)))
+            .collect();
         let mut album_state = ListState::default();
         if !albums.is_empty() {
             album_state.select(Some(0));
@@ -103,28 +324,93 @@ impl App {
             "Vinyl Vibes",
             "Spin It to Win It",
         ];
-        let mut rng = rand::thread_rng();
         let title_phrase = phrases.choose(&mut rng).unwrap().to_string();
-        Self {
+        let mut app = Self {
             albums,
             state: AppState::Browsing,
             selected_index: 0,
             playing_album: None,
-            playback_start: None,
-            pause_duration: Duration::from_secs(0),
             paused: false,
-            pause_start: None,
             album_list_state: album_state,
             song_list_state: song_state,
             current_sink: None,
+            queue: Vec::new(),
+            queue_offset: 0.0,
+            followed_track: None,
+            hooks: Hooks::new(&config.hooks),
+            announced_track: None,
+            now_playing_files: nowplaying::Writer::new(&config.now_playing),
+            artist: None,
+            history_file: config.history_file.as_deref().map(config::expand_home),
+            listening: history::Tracker::default(),
+            scrobbler: None,
+            notifier: None,
+            stats: None,
+            quit_requested: false,
+            shelf_changed: true,
             current_message: None,
             volume: 0.25, // initial volume 25%
-            current_song_index: 0,
             focus: Focus::Albums,
             title_phrase,
             playback_speed: 33.0,
-            pending_g: false,
+            keymap: Keymap::new(&config.keys)?,
+            pending_keys: Vec::new(),
+            pending_count: None,
+            awaiting_letter: None,
+            marks,
+            state_file,
+            datadir: datadir.to_path_buf(),
+            command_line: None,
+            command_history: Vec::new(),
+            history_pos: None,
+            completions: Vec::new(),
+            completion_pos: 0,
+            theme: Theme::from_config(&config.theme)?,
+            layout: config.layout,
+            overlay: None,
+            overlay_scroll: 0,
+            error_log: Vec::new(),
+            echo_errors: false,
+            details: None,
+            show_lyrics: false,
+            lyrics: None,
+            lyrics_scroll: 0,
             message_time: None,
+            seed,
+            rng,
+            shelf_area: Rect::default(),
+            shelf_offset: 0,
+            backside_area: Rect::default(),
+            backside_offset: 0,
+            progress_area: Rect::default(),
+            lyrics_area: Rect::default(),
+            last_click: None,
+            seek_preview: None,
+        };
+        app.load_last_played();
+        Ok(app)
+    }
+
+    // Takes when each album was last played from the history file, so
+    // digging favours albums not heard in a while across restarts too.
+    fn load_last_played(&mut self) {
+        let Some(ref path) = self.history_file else {
+            return;
+        };
+        let listens = match history::load(path) {
+            Ok(listens) => listens,
+            Err(e) => return self.log_error(format!("History: {}", e)),
+        };
+        let mut last: HashMap<&Path, u64> = HashMap::new();
+        for listen in &listens {
+            let ended = last.entry(&listen.album_path).or_default();
+            *ended = listen.ended.max(*ended);
+        }
+        for album in &mut self.albums {
+            if let Some(&ended) = last.get(album.path.as_path()) {
+                let ended = UNIX_EPOCH + Duration::from_secs(ended);
+                album.last_played = album.last_played.max(Some(ended));
+            }
         }
     }
 
@@ -133,6 +419,108 @@ impl App {
         self.message_time = Some(Instant::now());
     }
 
+    // Keeps the error for the log pane, the terminal belongs to the TUI.
+    fn log_error(&mut self, e: impl Display) {
+        if self.error_log.len() == MAX_LOG_ENTRIES {
+            self.error_log.remove(0);
+        }
+        if self.echo_errors {
+            eprintln!("{}", e);
+        }
+        self.error_log.push((Instant::now(), e.to_string()));
+    }
+
+    fn report(&mut self, e: impl Display) {
+        self.set_message(format!("Error: {}", e));
+        self.log_error(e);
+    }
+
+    // Runs the hook for `event` with the album, and the playing track if it
+    // is that album's, as LEVARI_* environment variables.
+    fn run_hook(&mut self, event: Event, album_idx: usize) {
+        let album = &self.albums[album_idx];
+        let mut env = vec![
+            ("LEVARI_ALBUM", album.name.clone()),
+            ("LEVARI_ALBUM_PATH", album.path.display().to_string()),
+            ("LEVARI_TRACK_COUNT", album.songs.len().to_string()),
+            ("LEVARI_BOOKMARKED", (album.bookmarked as u8).to_string()),
+        ];
+        if let Some(ref cover) = album.cover {
+            env.push(("LEVARI_COVER", cover.display().to_string()));
+        }
+        let track = self.playing_track().filter(|_| self.playing_album == Some(album_idx));
+        if let Some(track) = track {
+            let song = &album.songs[track];
+            env.push(("LEVARI_TRACK", song.title.clone()));
+            env.push(("LEVARI_TRACK_NUMBER", (track + 1).to_string()));
+            env.push(("LEVARI_TRACK_PATH", song.path.display().to_string()));
+            env.push(("LEVARI_TRACK_DURATION", song.duration.to_string()));
+            env.push(("LEVARI_POSITION", format!("{:.0}", self.track_position())));
+        }
+        let path = track.map(|t| album.songs[t].path.clone());
+        if let Err(e) = self.hooks.run(event, env, path) {
+            self.log_error(format!("Hook on_{}: {}", event.name(), e));
+        }
+    }
+
+    // Desktop notification for an inserted album or a new track on it.
+    fn notify(&self, event: Event, album_idx: usize) {
+        let Some(ref notifier) = self.notifier else {
+            return;
+        };
+        let album = &self.albums[album_idx];
+        let track = self.playing_track().filter(|_| self.playing_album == Some(album_idx));
+        let notification = match (event, track) {
+            (Event::Insert, _) => Notification::Insert {
+                album: album.name.clone(),
+                cover: album.cover.clone(),
+                track_count: album.songs.len(),
+                track: track.map(|t| album.songs[t].path.clone()),
+            },
+            (Event::TrackChange, Some(track)) => Notification::TrackChange {
+                album: album.name.clone(),
+                cover: album.cover.clone(),
+                title: album.songs[track].title.clone(),
+                number: track + 1,
+                track: album.songs[track].path.clone(),
+            },
+            _ => return,
+        };
+        notifier.send(notification);
+    }
+
+    fn show_overlay(&mut self, overlay: Overlay) {
+        self.overlay = Some(overlay);
+        self.overlay_scroll = 0;
+    }
+
+    // Probing every track takes a moment on big albums, so the result is
+    // kept until another album is opened. The real track lengths replace
+    // the estimates from the file size on the way.
+    fn show_details(&mut self) {
+        let album = &mut self.albums[self.selected_index];
+        if self.details.as_ref().is_none_or(|(path, _)| *path != album.path) {
+            let details = AlbumDetails::load(&album.path, album.songs.iter().map(|s| s.path.as_path()));
+            for (song, info) in album.songs.iter_mut().zip(&details.tracks) {
+                if let Some(duration) = info.as_ref().ok().and_then(|i| i.duration) {
+                    if !song.unplayable {
+                        song.duration = duration.round() as u64;
+                    }
+                }
+            }
+            self.details = Some((album.path.clone(), details));
+        }
+        self.show_overlay(Overlay::Details);
+    }
+
+    fn show_stats(&mut self) {
+        self.stats = self.history_file.as_deref().map(|path| match history::load(path) {
+            Ok(listens) => Ok(Stats::new(&listens)),
+            Err(e) => Err(e.to_string()),
+        });
+        self.show_overlay(Overlay::Stats);
+    }
+
     // --- Navigation Methods ---
     fn next_album(&mut self) {
         if self.albums.is_empty() {
@@ -148,7 +536,7 @@ impl App {
             return;
         }
         if self.selected_index == 0 {
-            self.focus = Focus::Vinyl;
+            self.set_focus(Focus::Vinyl);
         } else {
             self.selected_index -= 1;
             self.album_list_state.select(Some(self.selected_index));
@@ -167,23 +555,16 @@ impl App {
             self.album_list_state.select(Some(last));
         }
     }
-    fn half_page_down_album(&mut self) {
+    fn move_album(&mut self, delta: isize) {
         if self.albums.is_empty() {
             return;
         }
-        let half = self.albums.len() / 2;
-        let next_idx = std::cmp::min(self.selected_index + half.max(1), self.albums.len() - 1);
+        let next_idx = self.selected_index.saturating_add_signed(delta).min(self.albums.len() - 1);
         self.selected_index = next_idx;
         self.album_list_state.select(Some(next_idx));
     }
-    fn half_page_up_album(&mut self) {
-        if self.albums.is_empty() {
-            return;
-        }
-        let half = self.albums.len() / 2;
-        let next_idx = self.selected_index.saturating_sub(half.max(1));
-        self.selected_index = next_idx;
-        self.album_list_state.select(Some(next_idx));
+    fn go_to_album(&mut self, number: usize) {
+        self.move_album(number as isize - 1 - self.selected_index as isize);
     }
 
     fn next_song(&mut self) {
@@ -210,12 +591,47 @@ impl App {
         let prev = if cur == 0 { cur } else { cur - 1 };
         self.song_list_state.select(Some(prev));
     }
+    fn move_song(&mut self, delta: isize) {
+        let len = self.albums[self.selected_index].songs.len();
+        if self.focus != Focus::SongList || len == 0 {
+            return;
+        }
+        let cur = self.song_list_state.selected().unwrap_or(0);
+        self.song_list_state.select(Some(cur.saturating_add_signed(delta).min(len - 1)));
+    }
+
+    // Pages by the rendered height of the focused list, minus its borders.
+    fn page(&mut self, action: Action, count: usize) {
+        let area = match self.focus {
+            Focus::Albums => self.shelf_area,
+            Focus::SongList => self.backside_area,
+            Focus::Vinyl => return,
+        };
+        let height = (area.height.saturating_sub(2) as usize).max(1);
+        let step = match action {
+            Action::HalfPageDown | Action::HalfPageUp => (height / 2).max(1),
+            _ => height,
+        } * count;
+        let delta = match action {
+            Action::HalfPageDown | Action::PageDown => step as isize,
+            _ => -(step as isize),
+        };
+        match self.focus {
+            Focus::Albums => self.move_album(delta),
+            _ => self.move_song(delta),
+        }
+    }
 
     fn toggle_bookmark(&mut self) {
-        if self.focus != Focus::Albums || self.albums.is_empty() {
+        if self.focus != Focus::Albums {
             return;
         }
-        let album = &mut self.albums[self.selected_index];
+        self.toggle_bookmark_at(self.selected_index);
+    }
+    fn toggle_bookmark_at(&mut self, idx: usize) {
+        let Some(album) = self.albums.get_mut(idx) else {
+            return;
+        };
         album.bookmarked = !album.bookmarked;
         let msg = if album.bookmarked {
             format!("Bookmarked '{}'", album.name)
@@ -223,6 +639,7 @@ impl App {
             format!("Removed bookmark '{}'", album.name)
         };
         self.set_message(msg);
+        self.run_hook(Event::Bookmark, idx);
     }
     fn next_bookmark(&mut self) {
         if self.focus != Focus::Albums || self.albums.is_empty() {
@@ -268,29 +685,423 @@ impl App {
         }
     }
 
+    fn set_mark(&mut self, letter: char) {
+        if self.focus != Focus::Albums || self.albums.is_empty() {
+            return;
+        }
+        let album = &self.albums[self.selected_index];
+        self.marks.insert(letter, album.path.clone());
+        let msg = format!("Marked '{}' as '{}", album.name, letter);
+        match self.save_state() {
+            Ok(()) => self.set_message(msg),
+            Err(e) => self.set_message(format!("{}, but saving marks failed: {}", msg, e)),
+        }
+    }
+    fn jump_to_mark(&mut self, letter: char) {
+        let target = self.marks.get(&letter).and_then(|path| self.albums.iter().position(|a| &a.path == path));
+        match target {
+            Some(i) => {
+                self.selected_index = i;
+                self.album_list_state.select(Some(i));
+                self.focus = Focus::Albums;
+                self.set_message(format!("Jumped to mark '{} '{}'", letter, self.albums[i].name));
+            }
+            None => self.set_message(format!("Mark '{} is not set", letter)),
+        }
+    }
+    fn mark_of(&self, album: &Album) -> Option<char> {
+        self.marks.iter().filter(|(_, path)| **path == album.path).map(|(letter, _)| *letter).min()
+    }
+
+    fn save_state(&self) -> Result<(), Box<dyn Error>> {
+        let Some(ref path) = self.state_file else {
+            return Ok(());
+        };
+        let marks = self.marks.iter().map(|(letter, path)| (letter.to_string(), path.clone())).collect();
+        State { marks }.save(path)
+    }
+
+    // Jumps to a random album, favouring ones that haven't been played for a
+    // while (or at all) and, if asked, bookmarked ones.
+    fn dig_crate(&mut self, favor_bookmarks: bool) {
+        let candidates: Vec<usize> = (0..self.albums.len())
+            .filter(|&i| i != self.selected_index && Some(i) != self.playing_album)
+            .collect();
+        if candidates.is_empty() {
+            self.set_message("Nothing else to dig up in this crate.");
+            return;
+        }
+        let weights = candidates.iter().map(|&i| {
+            let album = &self.albums[i];
+            let recency = match album.last_played {
+                None => 4.0,
+                Some(t) => 0.25 + 2.75 * (t.elapsed().unwrap_or_default().as_secs_f64() / 7200.0).min(1.0),
+            };
+            if favor_bookmarks && album.bookmarked {
+                recency * 3.0
+            } else {
+                recency
+            }
+        });
+        let dist = match WeightedIndex::new(weights) {
+            Ok(dist) => dist,
+            Err(_) => return,
+        };
+        let i = candidates[dist.sample(&mut self.rng)];
+        self.selected_index = i;
+        self.album_list_state.select(Some(i));
+        self.focus = Focus::Albums;
+
+        let album = &self.albums[i];
+        let never = if self.history_file.is_some() { "never played" } else { "not played this session" };
+        let mut reasons = vec![match album.last_played.map(|t| t.elapsed().unwrap_or_default().as_secs()) {
+            None => never.to_string(),
+            Some(secs) if secs < 3600 => format!("last played {}m ago", secs / 60),
+            Some(secs) if secs < 2 * 86_400 => format!("last played {}h ago", secs / 3600),
+            Some(secs) => format!("last played {} days ago", secs / 86_400),
+        }];
+        if favor_bookmarks && album.bookmarked {
+            reasons.push("bookmarked".to_string());
+        }
+        self.set_message(format!("Dug up '{}' ({})", album.name, reasons.join(", ")));
+    }
+
+    // Panels hidden by the layout can't take the focus, keys would act on a
+    // list that isn't there.
     fn set_focus(&mut self, new_focus: Focus) {
-        self.focus = new_focus;
+        if self.can_focus(new_focus) {
+            self.focus = new_focus;
+        }
+    }
+
+    fn can_focus(&self, focus: Focus) -> bool {
+        match focus {
+            Focus::Vinyl => self.layout.show_player,
+            Focus::Albums => true,
+            Focus::SongList => self.layout.show_backside,
+        }
+    }
+
+    fn focus_backside_of_inserted(&mut self) {
+        if self.playing_album == Some(self.selected_index) {
+            self.set_focus(Focus::SongList);
+        } else {
+            self.set_message("That album is not inserted. Press ENTER to insert.");
+        }
+    }
+
+    fn open_backside(&mut self) {
+        if !self.can_focus(Focus::SongList) {
+            return;
+        }
+        self.set_focus(Focus::SongList);
+        let song_idx = match self.playing_track() {
+            Some(track) if self.playing_album == Some(self.selected_index) => track,
+            _ => 0,
+        };
+        self.song_list_state.select(Some(song_idx));
+    }
+
+    // --- Input ---
+    // Collects an optional count and then keys until they form a bound
+    // sequence, like vim's `5j` or `gg`. A key that breaks a sequence starts a
+    // new one, so a stray `g` doesn't swallow the next key.
+    fn feed_key(&mut self, key: Key) -> Option<Invocation> {
+        if let Some(action) = self.awaiting_letter.take() {
+            self.pending_keys.clear();
+            let count = self.pending_count.take();
+            return key.letter().map(|letter| Invocation { action, count, letter: Some(letter) });
+        }
+        if self.pending_keys.is_empty() {
+            if let Some(digit) = key.digit() {
+                let unbound = matches!(self.keymap.lookup(&[key]), Lookup::None);
+                if unbound && (digit != 0 || self.pending_count.is_some()) {
+                    let count = self.pending_count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+                    self.pending_count = Some(count.min(9999));
+                    return None;
+                }
+            }
+        }
+        // Popups scroll with single keys and close on any other, bound or not.
+        if self.overlay.is_some() {
+            self.pending_keys.clear();
+            return match self.keymap.lookup(&[key]) {
+                Lookup::Action(action @ (Action::Down | Action::Up)) => {
+                    Some(Invocation { action, count: self.pending_count.take(), letter: None })
+                }
+                _ => {
+                    self.pending_count = None;
+                    self.overlay = None;
+                    None
+                }
+            };
+        }
+        self.pending_keys.push(key);
+        match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Action(action) if action.takes_letter() => {
+                self.awaiting_letter = Some(action);
+                None
+            }
+            Lookup::Action(action) => {
+                self.pending_keys.clear();
+                Some(Invocation { action, count: self.pending_count.take(), letter: None })
+            }
+            Lookup::Pending => None,
+            Lookup::None => {
+                let retry = self.pending_keys.len() > 1;
+                self.pending_keys.clear();
+                if retry {
+                    self.feed_key(key)
+                } else {
+                    self.pending_count = None;
+                    None
+                }
+            }
+        }
+    }
+
+    // The count and keys typed so far, shown like vim's showcmd.
+    fn pending_input(&self) -> String {
+        let count = self.pending_count.map(|c| c.to_string()).unwrap_or_default();
+        count + &keymap::format_keys(&self.pending_keys)
     }
 
-    fn handle_shift_key(&mut self, key: char) {
-        match key {
-            'J' if self.focus == Focus::Vinyl => self.focus = Focus::Albums,
-            'K' if self.focus == Focus::Albums || self.focus == Focus::SongList => self.focus = Focus::Vinyl,
-            'L' if self.focus == Focus::Albums => {
-                if let Some(idx) = self.playing_album {
-                    if idx == self.selected_index {
-                        self.focus = Focus::SongList;
+    // Runs a bound action, interpreted for the focused panel. Motions and
+    // steps are repeated `count` times, `gg`/`G` with a count go to that album.
+    fn perform(&mut self, invocation: Invocation, stream_handle: &OutputStreamHandle) -> Result<(), Box<dyn Error>> {
+        let Invocation { action, count, letter } = invocation;
+        let n = count.unwrap_or(1).max(1);
+        if self.overlay.is_some() {
+            match action {
+                Action::Down => self.overlay_scroll = self.overlay_scroll.saturating_add(n as u16),
+                Action::Up => self.overlay_scroll = self.overlay_scroll.saturating_sub(n as u16),
+                _ => self.overlay = None,
+            }
+            return Ok(());
+        }
+        match action {
+            Action::Quit | Action::Cancel => {}
+            Action::Help => self.show_overlay(Overlay::Help),
+            Action::ErrorLog => self.show_overlay(Overlay::Log),
+            Action::Stats => self.show_stats(),
+            Action::Details => self.show_details(),
+            Action::Lyrics => self.toggle_lyrics(),
+            Action::LyricsDown => self.scroll_lyrics(n as isize),
+            Action::LyricsUp => self.scroll_lyrics(-(n as isize)),
+            Action::CommandLine => self.open_command_line(),
+            Action::Down => match self.focus {
+                Focus::Vinyl => self.set_focus(Focus::Albums),
+                Focus::Albums if count.is_some() => self.move_album(n as isize),
+                Focus::Albums => self.next_album(),
+                Focus::SongList => self.move_song(n as isize),
+            },
+            Action::Up => match self.focus {
+                Focus::Albums if count.is_some() => self.move_album(-(n as isize)),
+                Focus::Albums => self.previous_album(),
+                Focus::SongList => self.move_song(-(n as isize)),
+                Focus::Vinyl => {}
+            },
+            Action::Left => match self.focus {
+                Focus::SongList => self.set_focus(Focus::Albums),
+                Focus::Vinyl => self.eject_current_album(),
+                Focus::Albums => {}
+            },
+            Action::Right if self.focus == Focus::Albums => self.open_backside(),
+            Action::FocusDown if self.focus == Focus::Vinyl => self.set_focus(Focus::Albums),
+            Action::FocusUp if self.focus != Focus::Vinyl => self.set_focus(Focus::Vinyl),
+            Action::FocusLeft if self.focus == Focus::SongList => self.set_focus(Focus::Albums),
+            Action::FocusRight if self.focus == Focus::Albums => self.focus_backside_of_inserted(),
+            Action::Top | Action::Bottom if self.focus == Focus::Albums && count.is_some() => self.go_to_album(n),
+            Action::Top if self.focus == Focus::Albums => self.go_to_top_album(),
+            Action::Bottom if self.focus == Focus::Albums => self.go_to_bottom_album(),
+            Action::HalfPageDown | Action::HalfPageUp | Action::PageDown | Action::PageUp => self.page(action, n),
+            Action::PlayPause => self.space_action(stream_handle)?,
+            Action::Select => match self.focus {
+                Focus::Albums | Focus::Vinyl => {
+                    if self.playing_album == Some(self.selected_index) {
+                        self.eject_current_album();
                     } else {
-                        self.set_message("That album is not inserted. Press ENTER to insert.");
+                        self.insert_album(stream_handle)?;
                     }
+                }
+                Focus::SongList => self.skip_to_song(stream_handle)?,
+            },
+            Action::VolumeUp => (0..n).for_each(|_| self.increase_volume()),
+            Action::VolumeDown => (0..n).for_each(|_| self.decrease_volume()),
+            Action::SpeedUp => self.increase_speed(stream_handle),
+            Action::SpeedDown => self.decrease_speed(stream_handle),
+            Action::Bookmark => self.toggle_bookmark(),
+            Action::SetMark => self.set_mark(letter.unwrap_or('a')),
+            Action::JumpToMark => self.jump_to_mark(letter.unwrap_or('a')),
+            Action::NextBookmark => (0..n).for_each(|_| self.next_bookmark()),
+            Action::PrevBookmark => (0..n).for_each(|_| self.prev_bookmark()),
+            Action::JumpToPlaying => self.jump_to_playing_album(),
+            Action::Dig => self.dig_crate(false),
+            Action::DigBookmarked => self.dig_crate(true),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // --- Command Line ---
+    fn open_command_line(&mut self) {
+        self.command_line = Some(String::new());
+        self.history_pos = None;
+        self.completions.clear();
+    }
+
+    // Edits the command line, returns true when `:quit` was run.
+    fn command_key(&mut self, key: KeyEvent, stream_handle: &OutputStreamHandle) -> Result<bool, Box<dyn Error>> {
+        let Some(mut line) = self.command_line.take() else {
+            return Ok(false);
+        };
+        if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+            self.completions.clear();
+        }
+        match key.code {
+            KeyCode::Esc => return Ok(false),
+            KeyCode::Enter => return self.run_command(&line, stream_handle),
+            KeyCode::Backspace if line.is_empty() => return Ok(false),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => line.clear(),
+            KeyCode::Char(c) => line.push(c),
+            KeyCode::Tab | KeyCode::BackTab => {
+                if self.completions.is_empty() {
+                    let names: Vec<&str> = self.albums.iter().map(|a| a.name.as_str()).collect();
+                    self.completions = command::complete(&line, &names);
+                    self.completion_pos = 0;
+                } else if key.code == KeyCode::Tab {
+                    self.completion_pos = (self.completion_pos + 1) % self.completions.len();
                 } else {
-                    self.set_message("That album is not inserted. Press ENTER to insert.");
+                    self.completion_pos = (self.completion_pos + self.completions.len() - 1) % self.completions.len();
+                }
+                if let Some(completion) = self.completions.get(self.completion_pos) {
+                    line = completion.clone();
+                }
+                if self.completions.len() == 1 {
+                    self.completions.clear();
                 }
             }
-            'H' if self.focus == Focus::SongList => self.focus = Focus::Albums,
-            'G' if self.focus == Focus::Albums => self.go_to_bottom_album(),
+            KeyCode::Up | KeyCode::Down if !self.command_history.is_empty() => {
+                let last = self.command_history.len() - 1;
+                self.history_pos = match (key.code, self.history_pos) {
+                    (KeyCode::Up, None) => Some(last),
+                    (KeyCode::Up, Some(pos)) => Some(pos.saturating_sub(1)),
+                    (_, Some(pos)) if pos < last => Some(pos + 1),
+                    _ => None,
+                };
+                line = self.history_pos.map(|pos| self.command_history[pos].clone()).unwrap_or_default();
+            }
             _ => {}
         }
+        self.command_line = Some(line);
+        Ok(false)
+    }
+
+    fn run_command(&mut self, line: &str, stream_handle: &OutputStreamHandle) -> Result<bool, Box<dyn Error>> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(false);
+        }
+        self.command_history.retain(|h| h != line);
+        self.command_history.push(line.to_string());
+        let command = match command::parse(line) {
+            Ok(command) => command,
+            Err(e) => {
+                self.set_message(e);
+                return Ok(false);
+            }
+        };
+        match command {
+            Command::Quit => return Ok(true),
+            Command::Help => self.show_overlay(Overlay::Help),
+            Command::Log => self.show_overlay(Overlay::Log),
+            Command::Volume(percent) => self.set_volume(percent as f32 / 100.0),
+            Command::Rpm(rpm) => self.set_speed(rpm, stream_handle),
+            Command::Bookmark => self.toggle_bookmark_at(self.selected_index),
+            Command::Rescan => self.rescan()?,
+            Command::Insert(name) => self.insert_by_name(&name, stream_handle)?,
+            Command::Seek(seek) => {
+                if self.playing_album.is_none() {
+                    self.set_message("No album is inserted yet. Press ENTER to insert.");
+                    return Ok(false);
+                }
+                let position = match seek {
+                    Seek::To(secs) => secs,
+                    Seek::Forward(secs) => self.album_position() + secs,
+                    Seek::Back(secs) => (self.album_position() - secs).max(0.0),
+                };
+                self.seek(stream_handle, position)?;
+            }
+        }
+        Ok(false)
+    }
+
+    // Exact (case-insensitive) name first, otherwise a unique partial match.
+    fn insert_by_name(&mut self, name: &str, stream_handle: &OutputStreamHandle) -> Result<(), PlayerError> {
+        let needle = name.to_lowercase();
+        let exact = self.albums.iter().position(|a| a.name.to_lowercase() == needle);
+        let partial: Vec<usize> = (0..self.albums.len())
+            .filter(|&i| self.albums[i].name.to_lowercase().contains(&needle))
+            .collect();
+        let idx = match (exact, partial.as_slice()) {
+            (Some(i), _) | (None, &[i]) => i,
+            (None, []) => {
+                self.set_message(format!("No album matches '{}'", name));
+                return Ok(());
+            }
+            (None, many) => {
+                self.set_message(format!("{} albums match '{}', be more specific", many.len(), name));
+                return Ok(());
+            }
+        };
+        self.selected_index = idx;
+        self.album_list_state.select(Some(idx));
+        self.focus = Focus::Albums;
+        self.insert_album(stream_handle)
+    }
+
+    // Re-reads the library. Albums that are still there keep their place and
+    // bookmarks, new ones are shuffled onto the end of the shelf.
+    fn rescan(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut found = load_albums(&self.datadir)?;
+        if found.is_empty() {
+            self.set_message(format!("No albums found in {}, keeping the shelf", self.datadir.display()));
+            return Ok(());
+        }
+        let selected_path = self.albums[self.selected_index].path.clone();
+        let playing_path = self.playing_album.map(|i| self.albums[i].path.clone());
+        if let Some(ref path) = playing_path {
+            if !found.iter().any(|a| &a.path == path) {
+                self.eject_current_album();
+            }
+        }
+
+        let before = self.albums.len();
+        let mut shelf = Vec::with_capacity(found.len());
+        for old in std::mem::take(&mut self.albums) {
+            if let Some(pos) = found.iter().position(|a| a.path == old.path) {
+                let mut album = found.swap_remove(pos);
+                album.bookmarked = old.bookmarked;
+                album.last_played = old.last_played;
+                shelf.push(album);
+            }
+        }
+        let removed = before - shelf.len();
+        let added = found.len();
+        shuffle_albums(&mut found, &mut self.rng);
+        shelf.extend(found);
+        self.albums = shelf;
+        self.load_last_played();
+
+        self.playing_album = playing_path.and_then(|path| self.albums.iter().position(|a| a.path == path));
+        self.selected_index = self.albums.iter().position(|a| a.path == selected_path).unwrap_or(0);
+        self.album_list_state.select(Some(self.selected_index));
+        self.shelf_changed = true;
+        self.set_message(format!("Rescanned: {} new, {} removed", added, removed));
+        Ok(())
     }
 
     // --- Helper Functions ---
@@ -298,41 +1109,111 @@ impl App {
         self.playback_speed / 33.0
     }
 
-    fn effective_elapsed(&self) -> f64 {
-        if let Some(start) = self.playback_start {
-            let raw = start.elapsed();
-            let effective = if self.paused {
-                self.pause_start.unwrap_or(start).saturating_duration_since(start)
-            } else {
-                raw
-            }
-            .saturating_sub(self.pause_duration);
-            effective.as_secs_f64()
-        } else {
-            0.0
+    // Album index of the track being played, worked out from how many of
+    // the queued tracks the sink still holds.
+    fn playing_track(&self) -> Option<usize> {
+        let sink = self.current_sink.as_ref()?;
+        let played = self.queue.len().checked_sub(sink.len())?;
+        self.queue.get(played).copied()
+    }
+
+    // Seconds into the playing track. The sink counts output time, which
+    // differs from record time at anything but 33 RPM.
+    fn track_position(&self) -> f64 {
+        let Some(ref sink) = self.current_sink else {
+            return 0.0;
+        };
+        let offset = if sink.len() == self.queue.len() { self.queue_offset } else { 0.0 };
+        offset + sink.get_pos().as_secs_f64() * self.playback_factor() as f64
+    }
+
+    // Seconds into the inserted album, counting the tracks before the current one.
+    fn album_position(&self) -> f64 {
+        let Some(idx) = self.playing_album else {
+            return 0.0;
+        };
+        match self.playing_track() {
+            Some(track) => self.track_start(idx, track) + self.track_position(),
+            // The sink ran out, the album has played through.
+            None => self.album_duration() as f64,
         }
     }
 
+    // Album position at which `track` of album `idx` starts.
+    fn track_start(&self, idx: usize, track: usize) -> f64 {
+        self.albums[idx].songs.iter().take(track).map(|s| s.duration as f64).sum()
+    }
+
+    fn album_duration(&self) -> u64 {
+        self.playing_album
+            .map(|idx| self.albums[idx].songs.iter().map(|s| s.duration).sum())
+            .unwrap_or(0)
+    }
+
+    // Queues the album from `start_index` on. Tracks that fail to open or
+    // decode are logged, marked unplayable and left out, so one bad file
+    // doesn't stop the record.
     fn create_album_sink(
-        &self,
+        &mut self,
         stream_handle: &OutputStreamHandle,
-        album: &Album,
+        album_idx: usize,
         start_index: usize,
-    ) -> Result<Sink, Box<dyn Error>> {
+        start_offset: Duration,
+    ) -> Result<Loaded, PlayerError> {
         let sink = Sink::try_new(stream_handle)?;
         let factor = self.playback_factor();
-        for song in album.songs.iter().skip(start_index) {
-            let file = File::open(&song.path)?;
-            let source = Decoder::new(BufReader::new(file))?;
-            sink.append(source.speed(factor));
+        let mut tracks = Vec::new();
+        let mut skipped = 0;
+        for i in start_index..self.albums[album_idx].songs.len() {
+            let song = &mut self.albums[album_idx].songs[i];
+            if song.unplayable {
+                continue;
+            }
+            match open_song(&song.path) {
+                Ok(source) => {
+                    // The decoder knows the real length for most formats,
+                    // which beats the estimate from the file size.
+                    if let Some(total) = source.total_duration().map(|t| t.as_secs_f64().round() as u64) {
+                        self.shelf_changed |= song.duration != total;
+                        song.duration = total;
+                    }
+                    let skip = if i == start_index { start_offset } else { Duration::ZERO };
+                    sink.append(source.skip_duration(skip).speed(factor));
+                    tracks.push(i);
+                }
+                Err(e) => {
+                    // Zero length keeps the album positions of the other tracks right.
+                    song.unplayable = true;
+                    song.duration = 0;
+                    self.shelf_changed = true;
+                    skipped += 1;
+                    self.log_error(e);
+                }
+            }
+        }
+        if tracks.is_empty() {
+            return Err(PlayerError::NothingPlayable(self.albums[album_idx].name.clone()));
         }
         sink.set_volume(self.volume);
         sink.play();
-        Ok(sink)
+        // The offset only applies if the start track itself could be played.
+        let offset = if tracks[0] == start_index { start_offset.as_secs_f64() } else { 0.0 };
+        Ok(Loaded { sink, tracks, skipped, offset })
+    }
+
+    // Swaps in a freshly loaded sink, keeping it paused if the player is.
+    fn start_sink(&mut self, loaded: Loaded) -> usize {
+        if self.paused {
+            loaded.sink.pause();
+        }
+        self.queue_offset = loaded.offset;
+        self.queue = loaded.tracks;
+        self.current_sink = Some(loaded.sink);
+        loaded.skipped
     }
 
     // --- Player Actions ---
-    fn insert_album(&mut self, stream_handle: &OutputStreamHandle) -> Result<(), Box<dyn Error>> {
+    fn insert_album(&mut self, stream_handle: &OutputStreamHandle) -> Result<(), PlayerError> {
         if let Some(current) = self.playing_album {
             if current != self.selected_index {
                 self.eject_current_album();
@@ -340,222 +1221,695 @@ impl App {
                 return Ok(());
             }
         }
+        let loaded = self.create_album_sink(stream_handle, self.selected_index, 0, Duration::ZERO)?;
         self.playing_album = Some(self.selected_index);
         self.state = AppState::Playing;
-        let album = &self.albums[self.selected_index];
-        let sink = self.create_album_sink(stream_handle, album, 0)?;
-        self.playback_start = Some(Instant::now());
-        self.pause_duration = Duration::from_secs(0);
+        self.albums[self.selected_index].last_played = Some(SystemTime::now());
+        let name = self.albums[self.selected_index].name.clone();
         self.paused = false;
-        self.pause_start = None;
-        self.current_song_index = 0;
+        let skipped = self.start_sink(loaded);
         self.song_list_state.select(Some(0));
-        self.current_sink = Some(sink);
-        self.set_message(format!("Album '{}' inserted and playing.", album.name));
+        self.set_message(with_skipped(format!("Album '{}' inserted and playing.", name), skipped));
+        self.run_hook(Event::Insert, self.selected_index);
+        self.notify(Event::Insert, self.selected_index);
         Ok(())
     }
 
     fn eject_current_album(&mut self) {
         if let Some(idx) = self.playing_album {
+            self.run_hook(Event::Eject, idx);
             let name = self.albums[idx].name.clone();
             self.state = AppState::Browsing;
             self.playing_album = None;
-            self.playback_start = None;
-            self.pause_duration = Duration::from_secs(0);
             self.paused = false;
-            self.pause_start = None;
             self.current_sink.take();
+            self.queue.clear();
             self.set_message(format!("Album '{}' ejected.", name));
         }
     }
 
     // SPACE toggles pause
-    fn space_action(&mut self, _stream_handle: &OutputStreamHandle) -> Result<(), Box<dyn Error>> {
+    fn space_action(&mut self, _stream_handle: &OutputStreamHandle) -> Result<(), PlayerError> {
         self.toggle_pause();
         Ok(())
     }
 
     // Always recreates the sink so that skipping starts at the selected song.
-    fn skip_to_song(&mut self, stream_handle: &OutputStreamHandle) -> Result<(), Box<dyn Error>> {
-        self.playing_album = Some(self.selected_index);
+    fn skip_to_song(&mut self, stream_handle: &OutputStreamHandle) -> Result<(), PlayerError> {
         let album = &self.albums[self.selected_index];
         let song_index = self.song_list_state.selected().unwrap_or(0);
         if song_index >= album.songs.len() {
             return Ok(());
         }
         let song_title = album.songs[song_index].title.clone();
-        let sink = self.create_album_sink(stream_handle, album, song_index)?;
-        self.current_sink = Some(sink);
+        let loaded = self.create_album_sink(stream_handle, self.selected_index, song_index, Duration::ZERO)?;
+        self.playing_album = Some(self.selected_index);
         self.state = AppState::Playing;
-        self.playback_start = Some(Instant::now());
-        self.pause_duration = Duration::from_secs(0);
         self.paused = false;
-        self.pause_start = None;
-        self.current_song_index = song_index;
-        self.set_message(format!("Skipped to: '{}'", song_title));
+        let skipped = self.start_sink(loaded);
+        self.set_message(with_skipped(format!("Skipped to: '{}'", song_title), skipped));
+        Ok(())
+    }
+
+    fn seek(&mut self, stream_handle: &OutputStreamHandle, position: f64) -> Result<(), PlayerError> {
+        if self.playing_album.is_none() {
+            return Ok(());
+        }
+        let position = position.clamp(0.0, self.album_duration() as f64);
+        let skipped = self.restart_at(stream_handle, position)?;
+        let pos = position as u64;
+        self.set_message(with_skipped(format!("Seeked to {:02}:{:02}", pos / 60, pos % 60), skipped));
         Ok(())
     }
 
+    // Rebuilds the sink so that playback continues from the given album
+    // position, at the current speed. Returns how many tracks were skipped.
+    // Positions outside the album are clamped to it, whatever front end sent them.
+    fn restart_at(&mut self, stream_handle: &OutputStreamHandle, position: f64) -> Result<usize, PlayerError> {
+        let Some(idx) = self.playing_album else {
+            return Ok(0);
+        };
+        let album = &self.albums[idx];
+        if album.songs.is_empty() {
+            return Ok(0);
+        }
+        // NaN passes through clamp, `max` below turns it into the start of the album.
+        let position = position.clamp(0.0, self.album_duration() as f64);
+        let mut song_index = album.songs.len() - 1;
+        let mut song_start = 0.0;
+        for (i, song) in album.songs.iter().enumerate() {
+            if position < song_start + song.duration as f64 || i == album.songs.len() - 1 {
+                song_index = i;
+                break;
+            }
+            song_start += song.duration as f64;
+        }
+        let offset = (position - song_start).max(0.0);
+        let loaded = self.create_album_sink(stream_handle, idx, song_index, Duration::from_secs_f64(offset))?;
+        Ok(self.start_sink(loaded))
+    }
+
     fn increase_speed(&mut self, stream_handle: &OutputStreamHandle) {
-        self.playback_speed = match self.playback_speed {
+        let rpm = match self.playback_speed {
             33.0 => 45.0,
             45.0 => 78.0,
             78.0 => 78.0,
             _ => 33.0,
         };
-        self.set_message(format!("Speed: {:.0} RPM", self.playback_speed));
-        self.update_speed(stream_handle);
+        self.set_speed(rpm, stream_handle);
     }
     fn decrease_speed(&mut self, stream_handle: &OutputStreamHandle) {
-        self.playback_speed = match self.playback_speed {
+        let rpm = match self.playback_speed {
             78.0 => 45.0,
             45.0 => 33.0,
             33.0 => 33.0,
             _ => 33.0,
         };
-        self.set_message(format!("Speed: {:.0} RPM", self.playback_speed));
-        self.update_speed(stream_handle);
-    }
-    fn update_speed(&mut self, stream_handle: &OutputStreamHandle) {
-        if let Some(current_album_idx) = self.playing_album {
-            let album = &self.albums[current_album_idx];
-            let song_index = self.current_song_index;
-            let effective_elapsed = self.effective_elapsed();
-            let cumulative: f64 = album.songs.iter().take(song_index).map(|s| s.duration as f64).sum();
-            let offset_in_current = effective_elapsed - cumulative;
-            let factor = self.playback_factor();
-            let sink = Sink::try_new(stream_handle).unwrap();
-            if song_index < album.songs.len() {
-                let current_song = &album.songs[song_index];
-                let file = File::open(&current_song.path).unwrap();
-                let reader = BufReader::new(file);
-                let decoder = Decoder::new(reader).unwrap();
-                let current_source = decoder.skip_duration(Duration::from_secs_f64(offset_in_current));
-                sink.append(current_source.speed(factor));
-            }
-            for song in album.songs.iter().skip(song_index + 1) {
-                let file = File::open(&song.path).unwrap();
-                let source = Decoder::new(BufReader::new(file)).unwrap();
-                sink.append(source.speed(factor));
-            }
-            sink.set_volume(self.volume);
-            sink.play();
-            self.current_sink = Some(sink);
-            let new_start = Instant::now() - Duration::from_secs_f64(effective_elapsed);
-            self.playback_start = Some(new_start);
+        self.set_speed(rpm, stream_handle);
+    }
+    fn set_speed(&mut self, rpm: f32, stream_handle: &OutputStreamHandle) {
+        let position = self.album_position();
+        self.playback_speed = rpm;
+        match self.restart_at(stream_handle, position) {
+            Ok(skipped) => self.set_message(with_skipped(format!("Speed: {:.0} RPM", rpm), skipped)),
+            Err(e) => self.report(e),
         }
     }
 
     fn increase_volume(&mut self) {
-        self.volume = (self.volume + 0.01).min(2.0);
-        if let Some(ref sink) = self.current_sink {
-            sink.set_volume(self.volume);
-        }
-        self.set_message(format!("Volume: {}%", (self.volume * 100.0) as u32));
+        self.set_volume(self.volume + 0.01);
     }
     fn decrease_volume(&mut self) {
-        self.volume = (self.volume - 0.01).max(0.0);
+        self.set_volume(self.volume - 0.01);
+    }
+    fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 2.0);
         if let Some(ref sink) = self.current_sink {
             sink.set_volume(self.volume);
         }
-        self.set_message(format!("Volume: {}%", (self.volume * 100.0) as u32));
+        self.set_message(format!("Volume: {}%", (self.volume * 100.0).round() as u32));
     }
 
     fn toggle_pause(&mut self) {
-        if let Some(ref sink) = self.current_sink {
+        if let (Some(sink), Some(idx)) = (&self.current_sink, self.playing_album) {
             if self.paused {
                 sink.play();
-                if let Some(pause_start) = self.pause_start {
-                    self.pause_duration += pause_start.elapsed();
-                }
                 self.paused = false;
-                self.pause_start = None;
                 self.set_message("Playing...");
+                self.run_hook(Event::Resume, idx);
             } else {
                 sink.pause();
                 self.paused = true;
-                self.pause_start = Some(Instant::now());
                 self.set_message("Paused.");
+                self.run_hook(Event::Pause, idx);
             }
         } else {
             self.set_message("No album is inserted yet. Press ENTER to insert.");
         }
     }
 
-    fn on_tick(&mut self) {
-        if let Some(ts) = self.message_time {
-            if ts.elapsed() >= Duration::from_secs(3) {
-                self.current_message = None;
-                self.message_time = None;
-            }
-        }
-    }
-}
-
-fn render_vinyl_player(app: &App) -> String {
-    if let Some(play_idx) = app.playing_album {
-        let album = &app.albums[play_idx];
-        let cumulative: u64 = album.songs.iter().take(app.current_song_index).map(|s| s.duration).sum();
-        let current_elapsed = if app.playback_start.is_some() {
-            app.effective_elapsed() as u64
+    // Moves to the next or previous playable track of the inserted album.
+    // Going back more than a few seconds into a track restarts it instead.
+    fn change_track(&mut self, stream_handle: &OutputStreamHandle, forward: bool) -> Result<(), PlayerError> {
+        let (Some(idx), Some(track)) = (self.playing_album, self.playing_track()) else {
+            return Ok(());
+        };
+        let songs = &self.albums[idx].songs;
+        let target = if forward {
+            (track + 1..songs.len()).find(|&i| !songs[i].unplayable)
+        } else if self.track_position() > 3.0 {
+            Some(track)
         } else {
-            0
-        };
-        let total_elapsed = cumulative + current_elapsed;
-        let minutes = total_elapsed / 60;
-        let seconds = total_elapsed % 60;
-        let status = if app.paused { "Paused" } else { "Playing" };
-        format!(
-            "Album: {}\nPath: {}\n\nElapsed: {:02}:{:02}\nVolume: {}%\nRPM: {:.0} RPM\nStatus: {}",
-            album.name,
-            album.path.display(),
-            minutes,
-            seconds,
-            (app.volume * 100.0) as u32,
-            app.playback_speed,
-            status
-        )
-    } else {
-        "No album playing".to_string()
+            (0..track).rev().find(|&i| !songs[i].unplayable)
+        };
+        let Some(target) = target else {
+            return Ok(());
+        };
+        let title = songs[target].title.clone();
+        let start = self.track_start(idx, target);
+        let skipped = self.restart_at(stream_handle, start)?;
+        self.set_message(with_skipped(format!("Skipped to: '{}'", title), skipped));
+        Ok(())
     }
-}
 
-fn ui<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
-    let main_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Length(3), Constraint::Min(0), Constraint::Length(3)].as_ref())
-        .split(f.size());
+    // --- Remote ---
+    fn snapshot(&self) -> Snapshot {
+        let status = match (self.playing_album, self.paused) {
+            (None, _) => Status::Stopped,
+            (Some(_), true) => Status::Paused,
+            (Some(_), false) => Status::Playing,
+        };
+        Snapshot {
+            status,
+            album: self.playing_album.map(|index| self.album_snapshot(index)),
+            track: self.playing_track(),
+            position: self.track_position(),
+            volume: self.volume,
+            rpm: self.playback_speed,
+        }
+    }
 
-    let header_text = Spans::from(vec![
-        Span::styled("Levari", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
-        Span::raw(" - "),
-        Span::styled(&app.title_phrase, Style::default().fg(Color::Magenta)),
-    ]);
-    let header = Paragraph::new(header_text).block(Block::default().borders(Borders::BOTTOM));
-    f.render_widget(header, main_chunks[0]);
+    fn album_snapshot(&self, index: usize) -> AlbumSnapshot {
+        let album = &self.albums[index];
+        AlbumSnapshot {
+            index,
+            name: album.name.clone(),
+            path: album.path.clone(),
+            cover: album.cover.clone(),
+            tracks: album
+                .songs
+                .iter()
+                .map(|s| TrackSnapshot {
+                    title: s.title.clone(),
+                    path: s.path.clone(),
+                    duration: s.duration,
+                    playable: !s.unplayable,
+                })
+                .collect(),
+        }
+    }
 
-    let main_vertical = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Length(8), Constraint::Min(0)].as_ref())
-        .split(main_chunks[1]);
-    let player_border = if app.focus == Focus::Vinyl { Color::Magenta } else { Color::Yellow };
-    let vinyl_block = Block::default()
-        .borders(Borders::ALL)
-        .title("Player")
-        .border_style(Style::default().fg(player_border));
-    let vinyl_text = render_vinyl_player(app);
-    let vinyl_paragraph = Paragraph::new(vinyl_text).block(vinyl_block);
-    f.render_widget(vinyl_paragraph, main_vertical[0]);
+    fn shelf_snapshot(&self) -> Vec<AlbumSnapshot> {
+        (0..self.albums.len()).map(|i| self.album_snapshot(i)).collect()
+    }
 
-    let bottom_columns = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
-        .split(main_vertical[1]);
-    let album_border = if app.focus == Focus::Albums { Color::Magenta } else { Color::Yellow };
-    let album_block = Block::default()
+    fn handle_request(&mut self, request: Request, stream_handle: &OutputStreamHandle) -> Reply {
+        let result = match request {
+            Request::Play if self.playing_album.is_none() => self.insert_album(stream_handle),
+            Request::Play | Request::Pause => {
+                let pause = matches!(request, Request::Pause);
+                if self.current_sink.is_some() && self.paused != pause {
+                    self.toggle_pause();
+                }
+                Ok(())
+            }
+            Request::PlayPause if self.playing_album.is_none() => self.insert_album(stream_handle),
+            Request::PlayPause => {
+                self.toggle_pause();
+                Ok(())
+            }
+            Request::Stop => {
+                self.eject_current_album();
+                Ok(())
+            }
+            Request::Next => self.change_track(stream_handle, true),
+            Request::Previous => self.change_track(stream_handle, false),
+            Request::Seek(offset) => self.seek(stream_handle, (self.album_position() + offset).max(0.0)),
+            Request::SeekAlbum(position) => self.seek(stream_handle, position),
+            // Like MPRIS asks, positions past the end of the track are ignored
+            // rather than landing in a later one.
+            Request::SetPosition(track, position) => match self.playing_album {
+                Some(idx)
+                    if self.playing_track() == Some(track)
+                        && (0.0..=self.albums[idx].songs[track].duration as f64).contains(&position) =>
+                {
+                    self.seek(stream_handle, self.track_start(idx, track) + position)
+                }
+                _ => Ok(()),
+            },
+            Request::SkipTo(track) => match self.playing_album {
+                Some(idx) if track < self.albums[idx].songs.len() => {
+                    self.seek(stream_handle, self.track_start(idx, track))
+                }
+                Some(idx) => {
+                    let album = &self.albums[idx];
+                    return Err(format!("'{}' has {} tracks", album.name, album.songs.len()));
+                }
+                None => return Err("No album inserted".to_string()),
+            },
+            Request::Insert(path) => {
+                let Some(idx) = self.album_at(&path) else {
+                    return Err(format!("{} is not on the shelf", path.display()));
+                };
+                self.selected_index = idx;
+                self.album_list_state.select(Some(idx));
+                self.insert_album(stream_handle)
+            }
+            Request::Bookmark(path) => {
+                let idx = match path {
+                    Some(path) => {
+                        self.album_at(&path).ok_or_else(|| format!("{} is not on the shelf", path.display()))?
+                    }
+                    None => self.playing_album.unwrap_or(self.selected_index),
+                };
+                self.toggle_bookmark_at(idx);
+                Ok(())
+            }
+            Request::Volume(volume) => {
+                self.set_volume(volume);
+                Ok(())
+            }
+            Request::Rpm(rpm) => {
+                self.set_speed(rpm, stream_handle);
+                Ok(())
+            }
+            Request::Quit => {
+                self.quit_requested = true;
+                Ok(())
+            }
+        };
+        result.map_err(|e| {
+            self.report(&e);
+            e.to_string()
+        })
+    }
+
+    // Shelf index of the album in `dir`, however the path is spelled.
+    fn album_at(&self, dir: &Path) -> Option<usize> {
+        let wanted = fs::canonicalize(dir).ok()?;
+        self.albums.iter().position(|a| fs::canonicalize(&a.path).is_ok_and(|p| p == wanted))
+    }
+
+    // --- Mouse ---
+    fn handle_mouse(&mut self, event: MouseEvent, stream_handle: &OutputStreamHandle) -> Result<(), Box<dyn Error>> {
+        let (col, row) = (event.column, event.row);
+        let shelf = self.shelf_area.inner(&Margin { vertical: 1, horizontal: 1 });
+        let backside = self.backside_area.inner(&Margin { vertical: 1, horizontal: 1 });
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) if area_contains(self.progress_area, col, row) => {
+                self.seek_preview = Some(self.progress_at(col));
+            }
+            MouseEventKind::Drag(MouseButton::Left) if self.seek_preview.is_some() => {
+                self.seek_preview = Some(self.progress_at(col));
+            }
+            MouseEventKind::Up(MouseButton::Left) if self.seek_preview.is_some() => {
+                let ratio = self.seek_preview.take().unwrap_or(0.0);
+                self.seek(stream_handle, ratio * self.album_duration() as f64)?;
+            }
+            MouseEventKind::Down(MouseButton::Left) if area_contains(shelf, col, row) => {
+                let i = self.shelf_offset + (row - shelf.y) as usize;
+                if i >= self.albums.len() {
+                    return Ok(());
+                }
+                self.focus = Focus::Albums;
+                self.selected_index = i;
+                self.album_list_state.select(Some(i));
+                let double_click =
+                    matches!(self.last_click, Some((t, last)) if last == i && t.elapsed() < Duration::from_millis(400));
+                if double_click {
+                    self.last_click = None;
+                    self.insert_album(stream_handle)?;
+                } else {
+                    self.last_click = Some((Instant::now(), i));
+                }
+            }
+            MouseEventKind::Down(MouseButton::Left) if area_contains(backside, col, row) => {
+                let i = self.backside_offset + (row - backside.y) as usize;
+                if i >= self.albums[self.selected_index].songs.len() {
+                    return Ok(());
+                }
+                if !self.can_focus(Focus::SongList) {
+                    return Ok(());
+                }
+                self.set_focus(Focus::SongList);
+                self.song_list_state.select(Some(i));
+                self.skip_to_song(stream_handle)?;
+            }
+            MouseEventKind::ScrollDown if area_contains(self.shelf_area, col, row) => {
+                self.focus = Focus::Albums;
+                self.next_album();
+            }
+            MouseEventKind::ScrollUp if area_contains(self.shelf_area, col, row) => {
+                self.focus = Focus::Albums;
+                if self.selected_index > 0 {
+                    self.previous_album();
+                }
+            }
+            MouseEventKind::ScrollDown if area_contains(self.lyrics_area, col, row) => self.scroll_lyrics(1),
+            MouseEventKind::ScrollUp if area_contains(self.lyrics_area, col, row) => self.scroll_lyrics(-1),
+            MouseEventKind::ScrollDown if area_contains(self.backside_area, col, row) => {
+                self.set_focus(Focus::SongList);
+                self.next_song();
+            }
+            MouseEventKind::ScrollUp if area_contains(self.backside_area, col, row) => {
+                self.set_focus(Focus::SongList);
+                self.previous_song();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn progress_at(&self, col: u16) -> f64 {
+        let area = self.progress_area;
+        if area.width <= 1 {
+            return 0.0;
+        }
+        (col.saturating_sub(area.x) as f64 / (area.width - 1) as f64).clamp(0.0, 1.0)
+    }
+
+    fn on_tick(&mut self) {
+        if let Some(ts) = self.message_time {
+            if ts.elapsed() >= Duration::from_secs(3) {
+                self.current_message = None;
+                self.message_time = None;
+            }
+        }
+        self.follow_playing_track();
+        self.announce_track();
+        self.refresh_lyrics();
+        self.write_now_playing();
+        self.track_listening();
+        let mut failures: Vec<String> = self.hooks.failures().collect();
+        failures.extend(self.scrobbler.iter().flat_map(Scrobbler::failures));
+        failures.extend(self.notifier.iter().flat_map(Notifier::failures));
+        for failure in failures {
+            self.log_error(failure);
+        }
+    }
+
+    fn track_listening(&mut self) {
+        let playing = self.playing_album.zip(self.playing_track()).map(|(idx, track)| {
+            let (album, song) = (&self.albums[idx], &self.albums[idx].songs[track]);
+            let listen = Listen {
+                album: album.name.clone(),
+                album_path: album.path.clone(),
+                track: song.title.clone(),
+                track_path: song.path.clone(),
+                track_number: track + 1,
+                started: 0,
+                ended: 0,
+                heard: 0,
+                duration: song.duration,
+                rpm: self.playback_speed,
+                finished: false,
+            };
+            (listen, self.track_position())
+        });
+        if let Some(listen) = self.listening.update(playing) {
+            self.record_listen(listen);
+        }
+    }
+
+    fn record_listen(&mut self, listen: Listen) {
+        if let Some(Err(e)) = self.scrobbler.as_ref().map(|s| s.scrobble(&listen)) {
+            self.log_error(e);
+        }
+        let Some(ref path) = self.history_file else {
+            return;
+        };
+        if let Err(e) = history::append(path, &listen) {
+            self.log_error(format!("History {}: {}", path.display(), e));
+        }
+    }
+
+    fn write_now_playing(&mut self) {
+        if self.now_playing_files.is_none() {
+            return;
+        }
+        let song = self.playing_album.zip(self.playing_track()).map(|(a, t)| &self.albums[a].songs[t]);
+        if let Some(song) = song.filter(|s| self.artist.as_ref().is_none_or(|(path, _)| *path != s.path)) {
+            let artist = metadata::probe(&song.path).ok().and_then(|info| info.tags.artist);
+            self.artist = Some((song.path.clone(), artist));
+        }
+        let now = self.now_playing();
+        if let Some(e) = self.now_playing_files.as_mut().and_then(|files| files.update(now.as_ref())) {
+            self.log_error(e);
+        }
+    }
+
+    // What the player panel shows, see `NowPlaying`.
+    fn now_playing(&self) -> Option<NowPlaying> {
+        let idx = self.playing_album?;
+        let album = &self.albums[idx];
+        let track = self.playing_track();
+        let song = track.map(|t| &album.songs[t]);
+        let status = match track {
+            None => "Played through",
+            Some(_) if self.paused => "Paused",
+            Some(_) => "Playing",
+        };
+        Some(NowPlaying {
+            status,
+            album: album.name.clone(),
+            album_path: album.path.clone(),
+            cover: album.cover.clone(),
+            artist: song.and_then(|s| self.artist.as_ref().filter(|(path, _)| *path == s.path)?.1.clone()),
+            track: song.map(|s| s.title.clone()),
+            track_number: track.map(|t| t + 1),
+            track_count: album.songs.len(),
+            next: track.and_then(|t| album.songs[t + 1..].iter().find(|s| !s.unplayable)).map(|s| s.title.clone()),
+            elapsed: if song.is_some() { self.track_position().max(0.0) as u64 } else { 0 },
+            duration: song.map_or(0, |s| s.duration),
+            album_elapsed: self.album_position().max(0.0) as u64,
+            album_duration: self.album_duration(),
+            volume: (self.volume * 100.0) as u32,
+            rpm: self.playback_speed,
+        })
+    }
+
+    // Runs the track change hook when another track starts, whether the
+    // record moved on by itself or was skipped.
+    fn announce_track(&mut self) {
+        let (Some(idx), track) = (self.playing_album, self.playing_track()) else {
+            self.announced_track = None;
+            return;
+        };
+        let path = track.map(|t| self.albums[idx].songs[t].path.clone());
+        if path != self.announced_track {
+            // The insert notification covers the first track of an album.
+            let songs = &self.albums[idx].songs;
+            let same_album = self.announced_track.as_ref().is_some_and(|p| songs.iter().any(|s| s.path == *p));
+            self.announced_track = path;
+            if track.is_some() {
+                self.run_hook(Event::TrackChange, idx);
+                if same_album {
+                    self.notify(Event::TrackChange, idx);
+                }
+            }
+        }
+    }
+
+    fn toggle_lyrics(&mut self) {
+        self.show_lyrics = !self.show_lyrics;
+        self.refresh_lyrics();
+    }
+
+    // Loads the lyrics when the pane is open and another track starts.
+    fn refresh_lyrics(&mut self) {
+        if !self.show_lyrics {
+            return;
+        }
+        let Some(song) = self.playing_album.zip(self.playing_track()).map(|(a, t)| &self.albums[a].songs[t]) else {
+            return;
+        };
+        if self.lyrics.as_ref().is_none_or(|(path, _)| *path != song.path) {
+            self.lyrics = Some((song.path.clone(), Lyrics::load(&song.path)));
+            self.lyrics_scroll = 0;
+        }
+    }
+
+    fn scroll_lyrics(&mut self, delta: isize) {
+        if self.show_lyrics {
+            self.lyrics_scroll = self.lyrics_scroll.saturating_add_signed(delta as i16);
+        }
+    }
+
+    // Moves the backside cursor along when the record moves on to the next
+    // track, as long as the cursor was still on the one that just finished.
+    fn follow_playing_track(&mut self) {
+        let track = self.playing_track();
+        if track == self.followed_track {
+            return;
+        }
+        let viewing_playing = self.playing_album == Some(self.selected_index);
+        if viewing_playing && track.is_some() && self.song_list_state.selected() == self.followed_track {
+            self.song_list_state.select(track);
+        }
+        self.followed_track = track;
+    }
+}
+
+// Synced lyrics keep the current line in the middle of the pane, unsynced
+// ones stay where they were scrolled to.
+fn render_lyrics<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App, area: Rect) {
+    let block = Block::default().borders(Borders::ALL).title("Lyrics").border_style(app.theme.border);
+    let height = area.height.saturating_sub(2);
+    let playing = app.playing_album.zip(app.playing_track()).map(|(a, t)| &app.albums[a].songs[t]);
+    let placeholder = |text: String| Paragraph::new(Span::styled(text, app.theme.muted)).alignment(Alignment::Center);
+    let Some(song) = playing else {
+        f.render_widget(placeholder("Nothing playing".to_string()).block(block), area);
+        return;
+    };
+    let lyrics = match app.lyrics {
+        Some((ref path, Some(ref lyrics))) if *path == song.path => lyrics,
+        _ => {
+            f.render_widget(placeholder(format!("No lyrics for '{}'", song.title)).block(block), area);
+            return;
+        }
+    };
+
+    let (current, scroll) = if lyrics.is_synced() {
+        let current = lyrics.current_line(app.track_position());
+        (current, current.unwrap_or(0).saturating_sub(height as usize / 2) as u16)
+    } else {
+        let max_scroll = (lyrics.lines.len() as u16).saturating_sub(height);
+        app.lyrics_scroll = app.lyrics_scroll.min(max_scroll);
+        (None, app.lyrics_scroll)
+    };
+    let lines: Vec<Spans> = lyrics
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, (_, text))| match current {
+            Some(c) if c == i => Spans::from(Span::styled(text.clone(), app.theme.accent.add_modifier(Modifier::BOLD))),
+            Some(c) if i < c => Spans::from(Span::styled(text.clone(), app.theme.muted)),
+            _ => Spans::from(text.clone()),
+        })
+        .collect();
+    let text = Paragraph::new(lines).alignment(Alignment::Center).scroll((scroll, 0)).block(block);
+    f.render_widget(text, area);
+}
+
+fn render_vinyl_player(app: &App) -> String {
+    let Some(now) = app.now_playing() else {
+        return "No album playing".to_string();
+    };
+    let track = match (now.track_number, &now.track) {
+        (Some(number), Some(title)) => format!("Track {}/{}: {}", number, now.track_count, title),
+        _ => "Track: -".to_string(),
+    };
+    format!(
+        "Album: {}\nPath: {}\n{}\nNext: {}\nVolume: {}%  RPM: {:.0}  Status: {}",
+        now.album,
+        now.album_path.display(),
+        track,
+        now.next.as_deref().unwrap_or("-"),
+        now.volume,
+        now.rpm,
+        now.status
+    )
+}
+
+// Position label for the player gauges, with the time left after a slash.
+fn gauge_label(name: &str, position: f64, total: u64) -> String {
+    let pos = (position.max(0.0) as u64).min(total);
+    let left = total - pos;
+    format!("{} {:02}:{:02} / -{:02}:{:02}", name, pos / 60, pos % 60, left / 60, left % 60)
+}
+
+fn ui<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
+    let layout = app.layout;
+    let header_height = if layout.show_header { 3 } else { 0 };
+    let main_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(header_height), Constraint::Min(0), Constraint::Length(3)].as_ref())
+        .split(f.size());
+
+    if layout.show_header {
+        let header_text = Spans::from(vec![
+            Span::styled("Levari", app.theme.title),
+            Span::raw(" - "),
+            Span::styled(&app.title_phrase, app.theme.accent),
+            Span::styled(format!("  [seed {}]", app.seed), app.theme.muted),
+        ]);
+        let header = Paragraph::new(header_text).block(Block::default().borders(Borders::BOTTOM));
+        f.render_widget(header, main_chunks[0]);
+    }
+
+    let player_height = if layout.show_player { layout.player_height } else { 0 };
+    let main_vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(player_height), Constraint::Min(0)].as_ref())
+        .split(main_chunks[1]);
+    app.progress_area = Rect::default();
+    if layout.show_player {
+        let vinyl_block = Block::default()
+            .borders(Borders::ALL)
+            .title("Player")
+            .border_style(app.theme.border(app.focus == Focus::Vinyl));
+        let vinyl_inner = vinyl_block.inner(main_vertical[0]);
+        f.render_widget(vinyl_block, main_vertical[0]);
+        let vinyl_rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1), Constraint::Length(1)].as_ref())
+            .split(vinyl_inner);
+        let vinyl_text = render_vinyl_player(app);
+        f.render_widget(Paragraph::new(vinyl_text), vinyl_rows[0]);
+        if let Some(idx) = app.playing_album {
+            let ratio_of = |pos: f64, total: u64| if total > 0 { (pos / total as f64).clamp(0.0, 1.0) } else { 0.0 };
+            if let Some(track) = app.playing_track() {
+                let total = app.albums[idx].songs[track].duration;
+                let pos = app.track_position();
+                let track_gauge = LineGauge::default()
+                    .gauge_style(app.theme.accent)
+                    .label(gauge_label("Track", pos, total))
+                    .ratio(ratio_of(pos, total));
+                f.render_widget(track_gauge, vinyl_rows[1]);
+            }
+            let total = app.album_duration();
+            let ratio = app.seek_preview.unwrap_or_else(|| ratio_of(app.album_position(), total));
+            let album_gauge = LineGauge::default()
+                .gauge_style(app.theme.accent)
+                .label(gauge_label("Album", ratio * total as f64, total))
+                .ratio(ratio);
+            f.render_widget(album_gauge, vinyl_rows[2]);
+            app.progress_area = vinyl_rows[2];
+        }
+    }
+
+    let show_right = layout.show_backside || app.show_lyrics;
+    let shelf_width = if show_right { layout.shelf_width } else { 100 };
+    let bottom_columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(shelf_width), Constraint::Percentage(100 - shelf_width)].as_ref())
+        .split(main_vertical[1]);
+    // The lyrics share the right column with the backside, below it.
+    let backside_height = if !layout.show_backside {
+        0
+    } else if app.show_lyrics {
+        bottom_columns[1].height * 2 / 5
+    } else {
+        bottom_columns[1].height
+    };
+    let right_rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(backside_height), Constraint::Min(0)].as_ref())
+        .split(bottom_columns[1]);
+    let album_block = Block::default()
         .borders(Borders::ALL)
         .title("Shelf")
-        .border_style(Style::default().fg(album_border));
+        .border_style(app.theme.border(app.focus == Focus::Albums));
     let album_items: Vec<ListItem> = app
         .albums
         .iter()
@@ -565,55 +1919,459 @@ fn ui<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
             if album.bookmarked {
                 name.push_str(" [*]");
             }
+            if let Some(letter) = app.mark_of(album) {
+                name.push_str(&format!(" ['{}]", letter));
+            }
             if app.playing_album == Some(i) {
                 name.push_str(" [INSERTED]");
-                ListItem::new(Spans::from(Span::styled(name, Style::default().fg(Color::Magenta))))
+                ListItem::new(Spans::from(Span::styled(name, app.theme.accent)))
             } else {
                 ListItem::new(Spans::from(Span::raw(name)))
             }
         })
         .collect();
+    app.shelf_area = bottom_columns[0];
+    let shelf_height = bottom_columns[0].height.saturating_sub(2) as usize;
+    app.shelf_offset = list_offset(app.shelf_offset, app.selected_index, shelf_height);
     let albums_list = List::new(album_items)
         .block(album_block)
-        .highlight_style(Style::default().fg(Color::Magenta))
+        .highlight_style(app.theme.highlight)
         .highlight_symbol(">> ");
     f.render_stateful_widget(albums_list, bottom_columns[0], &mut app.album_list_state);
 
-    let song_border = if app.focus == Focus::SongList { Color::Magenta } else { Color::Yellow };
-    let album_for_songs = &app.albums[app.selected_index];
-    let song_block = Block::default()
-        .borders(Borders::ALL)
-        .title("Backside")
-        .border_style(Style::default().fg(song_border));
-    let mut cum = 0;
-    let song_items: Vec<ListItem> = album_for_songs
-        .songs
-        .iter()
-        .enumerate()
-        .map(|(i, song)| {
-            let start_time = cum;
-            cum += song.duration;
-            let minutes = start_time / 60;
-            let seconds = start_time % 60;
-            let mut line = format!("{} [{:02}:{:02}]", song.title, minutes, seconds);
-            if app.focus == Focus::SongList && Some(i) == app.song_list_state.selected() {
-                line = format!("> {}", line);
-            }
-            ListItem::new(line)
-        })
-        .collect();
-    let songs_list = List::new(song_items)
-        .block(song_block)
-        .highlight_style(Style::default().fg(Color::Magenta));
-    f.render_widget(songs_list, bottom_columns[1]);
+    app.backside_area = Rect::default();
+    if layout.show_backside {
+        let album_for_songs = &app.albums[app.selected_index];
+        let song_block = Block::default()
+            .borders(Borders::ALL)
+            .title("Backside")
+            .border_style(app.theme.border(app.focus == Focus::SongList));
+        let playing_track = app.playing_track().filter(|_| app.playing_album == Some(app.selected_index));
+        let mut cum = 0;
+        let song_items: Vec<ListItem> = album_for_songs
+            .songs
+            .iter()
+            .enumerate()
+            .map(|(i, song)| {
+                let start_time = cum;
+                cum += song.duration;
+                let mut line = format!(
+                    "{} [{:02}:{:02}] ({}:{:02})",
+                    song.title,
+                    start_time / 60,
+                    start_time % 60,
+                    song.duration / 60,
+                    song.duration % 60
+                );
+                if app.focus == Focus::SongList && Some(i) == app.song_list_state.selected() {
+                    line = format!("> {}", line);
+                }
+                if song.unplayable {
+                    return ListItem::new(format!("{} (unplayable)", line)).style(app.theme.muted);
+                }
+                if Some(i) == playing_track {
+                    return ListItem::new(format!("{} [PLAYING]", line))
+                        .style(app.theme.accent.add_modifier(Modifier::BOLD));
+                }
+                ListItem::new(line)
+            })
+            .collect();
+        let backside_height = right_rows[0].height.saturating_sub(2) as usize;
+        let selected_song = app.song_list_state.selected().unwrap_or(0);
+        app.backside_offset = list_offset(app.backside_offset, selected_song, backside_height);
+        let songs_list = List::new(song_items).block(song_block);
+        f.render_stateful_widget(songs_list, right_rows[0], &mut app.song_list_state);
+        app.backside_area = right_rows[0];
+    }
+
+    app.lyrics_area = Rect::default();
+    if app.show_lyrics {
+        render_lyrics(f, app, right_rows[1]);
+        app.lyrics_area = right_rows[1];
+    }
 
-    let footer_text = if let Some(ref msg) = app.current_message {
+    let pending = app.pending_input();
+    let footer_text = if let Some(ref line) = app.command_line {
+        let area = main_chunks[2];
+        let cursor_x = area.x + 1 + line.chars().count() as u16;
+        f.set_cursor(cursor_x.min(area.right().saturating_sub(1)), area.y + 1);
+        Spans::from(vec![Span::raw(format!(":{}", line))])
+    } else if !pending.is_empty() {
+        Spans::from(vec![Span::styled(pending, app.theme.accent)])
+    } else if let Some(ref msg) = app.current_message {
         Spans::from(vec![Span::raw(msg)])
     } else {
-        Spans::from(vec![Span::raw("Space = Play/Pause  |  Enter = Insert/Eject/Skip  |  h/j/k/l = Navigate  |  Shift+H/J/K/L = Change Focus  |  m = Bookmark  |  n/N = Next/Prev Bookmark  |  +/- = Volume  |  >/< = Speed  |  q = Quit")])
+        Spans::from(vec![Span::raw(footer_hint(&app.keymap))])
     };
-    let footer = Paragraph::new(footer_text).block(Block::default().borders(Borders::TOP));
+    let mut footer_lines = vec![footer_text];
+    if !app.completions.is_empty() {
+        let spans = app
+            .completions
+            .iter()
+            .enumerate()
+            .flat_map(|(i, c)| {
+                let label = c.split_once(' ').map(|(_, arg)| arg).unwrap_or(c).to_string();
+                let style = if i == app.completion_pos { app.theme.highlight } else { app.theme.muted };
+                [Span::styled(label, style), Span::raw("  ")]
+            })
+            .collect::<Vec<_>>();
+        footer_lines.push(Spans::from(spans));
+    }
+    let footer = Paragraph::new(footer_lines).block(Block::default().borders(Borders::TOP));
     f.render_widget(footer, main_chunks[2]);
+
+    match app.overlay {
+        Some(Overlay::Help) => render_help(f, app),
+        Some(Overlay::Log) => render_log(f, app),
+        Some(Overlay::Details) => render_details(f, app),
+        Some(Overlay::Stats) => render_stats(f, app),
+        None => {}
+    }
+}
+
+fn footer_hint(keymap: &Keymap) -> String {
+    let hints = [
+        (Action::Help, "Help"),
+        (Action::PlayPause, "Play/Pause"),
+        (Action::Select, "Insert/Eject/Skip"),
+        (Action::Dig, "Dig"),
+        (Action::Quit, "Quit"),
+    ];
+    hints
+        .iter()
+        .filter_map(|(action, label)| keymap.keys_for(*action).first().map(|key| format!("{} = {}", key, label)))
+        .collect::<Vec<_>>()
+        .join("  |  ")
+}
+
+// Lists every binding from the action table, grouped by where it applies.
+fn render_help<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
+    let groups = [
+        (None, "Everywhere"),
+        (Some(Focus::Vinyl), "Player"),
+        (Some(Focus::Albums), "Shelf"),
+        (Some(Focus::SongList), "Backside"),
+    ];
+    let mut lines = vec![Spans::from(Span::styled(
+        "Moves, paging, volume and bookmark jumps take a count, e.g. 5j or 3n.",
+        app.theme.muted,
+    ))];
+    for (focus, title) in groups {
+        lines.push(Spans::from(""));
+        lines.push(Spans::from(Span::styled(title, app.theme.accent.add_modifier(Modifier::BOLD))));
+        for info in ACTIONS {
+            let keys = app.keymap.keys_for(info.action);
+            if keys.is_empty() {
+                continue;
+            }
+            for (_, text) in info.help.iter().filter(|(f, _)| *f == focus) {
+                lines.push(Spans::from(vec![
+                    Span::styled(format!("  {:<16}", keys.join(" ")), app.theme.title),
+                    Span::raw(*text),
+                ]));
+            }
+        }
+    }
+    render_popup(f, app, "Help", 64, lines);
+}
+
+// Newest first, so the latest error is on top without scrolling.
+fn render_log<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
+    let mut lines: Vec<Spans> = app
+        .error_log
+        .iter()
+        .rev()
+        .map(|(time, msg)| {
+            Spans::from(vec![
+                Span::styled(format!("{:>8}  ", format_ago(time.elapsed())), app.theme.muted),
+                Span::raw(msg.clone()),
+            ])
+        })
+        .collect();
+    if lines.is_empty() {
+        lines.push(Spans::from(Span::styled("No errors so far.", app.theme.muted)));
+    }
+    render_popup(f, app, "Error log", 64, lines);
+}
+
+fn render_details<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
+    let album = &app.albums[app.selected_index];
+    let Some((_, ref details)) = app.details else {
+        return;
+    };
+    let heading_style = app.theme.accent.add_modifier(Modifier::BOLD);
+    let heading = |text: &str| Spans::from(Span::styled(text.to_string(), heading_style));
+    let field = |name: &str, value: String| {
+        Spans::from(vec![Span::styled(format!("{:<10}", name), app.theme.title), Span::raw(value)])
+    };
+
+    let mut lines = vec![heading(&album.name), field("Path", album.path.display().to_string())];
+    let tags = details.tags();
+    for (name, value) in [
+        ("Artist", tags.album_artist.or(tags.artist)),
+        ("Album", tags.album),
+        ("Date", tags.date),
+        ("Genre", tags.genre),
+        ("Label", tags.label),
+    ] {
+        if let Some(value) = value {
+            lines.push(field(name, value));
+        }
+    }
+    let runtime: u64 = album.songs.iter().map(|s| s.duration).sum();
+    let size: u64 = details.tracks.iter().flatten().map(|t| t.size).sum();
+    lines.push(field("Tracks", album.songs.len().to_string()));
+    lines.push(field("Runtime", format!("{}:{:02}", runtime / 60, runtime % 60)));
+    lines.push(field("Size", format_size(size)));
+    // Distinct formats with how many tracks use each, e.g. "FLAC 44.1 kHz 16-bit (12)".
+    let mut formats: Vec<(String, usize)> = Vec::new();
+    for info in details.tracks.iter().flatten() {
+        let format = format_audio(info);
+        match formats.iter_mut().find(|(f, _)| *f == format) {
+            Some((_, n)) => *n += 1,
+            None => formats.push((format, 1)),
+        }
+    }
+    for (i, (format, n)) in formats.into_iter().enumerate() {
+        lines.push(field(if i == 0 { "Format" } else { "" }, format!("{} ({})", format, n)));
+    }
+
+    lines.push(Spans::from(""));
+    lines.push(heading("Tracks"));
+    for (i, (song, info)) in album.songs.iter().zip(&details.tracks).enumerate() {
+        let detail = match info {
+            Ok(info) => format!("{}  {}", format_audio(info), format_size(info.size)),
+            Err(e) => e.clone(),
+        };
+        let title = info.as_ref().ok().and_then(|i| i.tags.title.clone()).unwrap_or_else(|| song.title.clone());
+        lines.push(Spans::from(vec![
+            Span::styled(format!("{:>3} ", i + 1), app.theme.muted),
+            Span::raw(format!("{}  {}:{:02}  ", title, song.duration / 60, song.duration % 60)),
+            Span::styled(detail, app.theme.muted),
+        ]));
+    }
+
+    for (name, text) in &details.notes {
+        lines.push(Spans::from(""));
+        lines.push(heading(name));
+        lines.extend(text.lines().map(|line| Spans::from(line.to_string())));
+    }
+    render_popup(f, app, "Details", 100, lines);
+}
+
+fn format_audio(info: &metadata::TrackInfo) -> String {
+    let mut parts = vec![info.format.clone()];
+    if let Some(rate) = info.sample_rate {
+        parts.push(format!("{} kHz", rate as f64 / 1000.0));
+    }
+    if let Some(bits) = info.bits_per_sample {
+        parts.push(format!("{}-bit", bits));
+    }
+    match info.channels {
+        Some(1) => parts.push("mono".to_string()),
+        Some(2) => parts.push("stereo".to_string()),
+        Some(n) => parts.push(format!("{} ch", n)),
+        None => {}
+    }
+    parts.join(" ")
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.1} GiB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1u64 << 20) as f64),
+        b => format!("{} KiB", b / 1024),
+    }
+}
+
+fn render_stats<B: tui::backend::Backend>(f: &mut tui::Frame<B>, app: &mut App) {
+    let muted = |text: String| Spans::from(Span::styled(text, app.theme.muted));
+    let stats = match app.stats {
+        Some(Ok(ref stats)) => stats,
+        Some(Err(ref e)) => return render_popup(f, app, "Statistics", 64, vec![Spans::from(e.clone())]),
+        None => {
+            let lines = vec![muted("Set history_file in the config to keep a listening log.".to_string())];
+            return render_popup(f, app, "Statistics", 64, lines);
+        }
+    };
+    let heading_style = app.theme.accent.add_modifier(Modifier::BOLD);
+    let heading = |text: &str| Spans::from(Span::styled(text.to_string(), heading_style));
+    let hours = |secs: u64| match secs {
+        s if s < 3600 => format!("{}m", s / 60),
+        s => format!("{:.1}h", s as f64 / 3600.0),
+    };
+    let bar = |secs: u64, max: u64, width: usize| "█".repeat((secs * width as u64).div_ceil(max.max(1)) as usize);
+
+    let mut lines = vec![heading("Listening")];
+    let days = stats.since.map(|since| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        now.saturating_sub(since) / 86_400 + 1
+    });
+    let days = match days.unwrap_or(0) {
+        1 => "today".to_string(),
+        n => format!("in the last {} days", n),
+    };
+    lines.push(Spans::from(format!("{} over {} tracks {}", hours(stats.heard), stats.listens, days)));
+
+    lines.push(Spans::default());
+    lines.push(heading("Most played"));
+    for album in stats.albums.iter().take(10) {
+        lines.push(Spans::from(vec![
+            Span::styled(format!("{:>7}  ", hours(album.heard)), app.theme.title),
+            Span::raw(album.name.clone()),
+            Span::styled(format!("  {} tracks", album.tracks), app.theme.muted),
+        ]));
+    }
+    if stats.albums.is_empty() {
+        lines.push(muted("Nothing yet".to_string()));
+    }
+
+    let played: HashSet<&Path> = stats.albums.iter().map(|a| a.path.as_path()).collect();
+    let mut neglected: Vec<&str> =
+        app.albums.iter().filter(|a| !played.contains(a.path.as_path())).map(|a| a.name.as_str()).collect();
+    neglected.sort_unstable_by_key(|name| name.to_lowercase());
+    lines.push(Spans::default());
+    lines.push(heading(&format!("Never played ({} of {})", neglected.len(), app.albums.len())));
+    lines.extend(neglected.iter().map(|name| Spans::from(name.to_string())));
+
+    lines.push(Spans::default());
+    lines.push(heading("By weekday"));
+    let busiest = stats.weekdays.iter().copied().max().unwrap_or(0);
+    for (day, secs) in ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"].iter().zip(stats.weekdays) {
+        lines.push(Spans::from(vec![
+            Span::styled(format!("{}  ", day), app.theme.title),
+            Span::styled(bar(secs, busiest, 40), app.theme.accent),
+            Span::styled(format!(" {}", hours(secs)), app.theme.muted),
+        ]));
+    }
+
+    lines.push(Spans::default());
+    lines.push(heading("By hour"));
+    let busiest = stats.hours.iter().copied().max().unwrap_or(0);
+    let levels = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let spark: String = stats
+        .hours
+        .iter()
+        .flat_map(|&secs| {
+            let level = levels[(secs * 8).div_ceil(busiest.max(1)) as usize];
+            [level, level]
+        })
+        .collect();
+    lines.push(Spans::from(Span::styled(spark, app.theme.accent)));
+    lines.push(muted("0     3     6     9     12    15    18    21".to_string()));
+    render_popup(f, app, "Statistics", 64, lines);
+}
+
+fn render_popup<B: tui::backend::Backend>(
+    f: &mut tui::Frame<B>,
+    app: &mut App,
+    name: &str,
+    max_width: u16,
+    lines: Vec<Spans>,
+) {
+    let size = f.size();
+    let width = size.width.saturating_sub(4).min(max_width);
+    // Wrapped here rather than by the paragraph, so the scroll limit counts rows.
+    let lines = wrap_lines(lines, width.saturating_sub(2) as usize);
+    let height = size.height.saturating_sub(2).min(lines.len() as u16 + 2);
+    let area = Rect::new((size.width - width) / 2, (size.height - height) / 2, width, height);
+    let max_scroll = (lines.len() as u16).saturating_sub(height.saturating_sub(2));
+    app.overlay_scroll = app.overlay_scroll.min(max_scroll);
+    let first_key = |action| app.keymap.keys_for(action).into_iter().next().unwrap_or_default();
+    let (down, up) = (first_key(Action::Down), first_key(Action::Up));
+    let title = format!("{} ({}/{} to scroll, any other key to close)", name, down, up);
+    let popup = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title).border_style(app.theme.focused))
+        .scroll((app.overlay_scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+// Breaks styled lines into rows of at most `width` columns, at the last
+// space that fits, or anywhere in words longer than a row.
+fn wrap_lines(lines: Vec<Spans>, width: usize) -> Vec<Spans<'static>> {
+    let mut rows = Vec::new();
+    for line in lines {
+        let chars: Vec<(char, Style)> =
+            line.0.iter().flat_map(|span| span.content.chars().map(move |c| (c, span.style))).collect();
+        let mut start = 0;
+        loop {
+            let (mut end, mut columns, mut last_space) = (start, 0, None);
+            while let Some(&(c, _)) = chars.get(end) {
+                let w = c.width().unwrap_or(0);
+                if columns + w > width {
+                    break;
+                }
+                if c == ' ' {
+                    last_space = Some(end);
+                }
+                columns += w;
+                end += 1;
+            }
+            if end < chars.len() {
+                match last_space {
+                    Some(space) if space > start => end = space + 1,
+                    // Even one character is too wide, it goes on a row of its own.
+                    _ if end == start => end += 1,
+                    _ => {}
+                }
+            }
+            rows.push(styled_row(&chars[start..end]));
+            start = end;
+            if start >= chars.len() {
+                break;
+            }
+        }
+    }
+    rows
+}
+
+fn styled_row(chars: &[(char, Style)]) -> Spans<'static> {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    for &(c, style) in chars {
+        match spans.last_mut() {
+            Some(span) if span.style == style => span.content.to_mut().push(c),
+            _ => spans.push(Span::styled(c.to_string(), style)),
+        }
+    }
+    Spans::from(spans)
+}
+
+fn format_ago(elapsed: Duration) -> String {
+    match elapsed.as_secs() {
+        s if s < 60 => format!("{}s ago", s),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s => format!("{}h ago", s / 3600),
+    }
+}
+
+fn area_contains(area: Rect, col: u16, row: u16) -> bool {
+    col >= area.left() && col < area.right() && row >= area.top() && row < area.bottom()
+}
+
+// Mirrors how `List` scrolls its `ListState`, whose offset is private, so that
+// clicks can be mapped back to list rows.
+fn list_offset(offset: usize, selected: usize, height: usize) -> usize {
+    if height == 0 || selected < offset {
+        selected
+    } else if selected >= offset + height {
+        selected + 1 - height
+    } else {
+        offset
+    }
+}
+
+fn open_song(path: &Path) -> Result<Decoder<BufReader<File>>, PlayerError> {
+    let file = File::open(path).map_err(|e| PlayerError::Open(path.to_path_buf(), e))?;
+    Decoder::new(BufReader::new(file)).map_err(|e| PlayerError::Decode(path.to_path_buf(), e))
+}
+
+fn with_skipped(msg: String, skipped: usize) -> String {
+    match skipped {
+        0 => msg,
+        1 => format!("{} Skipped 1 unplayable track, see the error log.", msg),
+        n => format!("{} Skipped {} unplayable tracks, see the error log.", msg, n),
+    }
 }
 
 fn load_albums(dir: &Path) -> Result<Vec<Album>, Box<dyn Error>> {
@@ -675,6 +2433,7 @@ fn load_album(dir: &Path) -> Result<Album, Box<dyn Error>> {
                         title: song_title,
                         duration,
                         path: path.clone(),
+                        unplayable: false,
                     });
                 }
             }
@@ -687,6 +2446,7 @@ fn load_album(dir: &Path) -> Result<Album, Box<dyn Error>> {
         cover,
         songs,
         bookmarked: false,
+        last_played: None,
     })
 }
 
@@ -717,180 +2477,307 @@ fn natural_order(a: &Song, b: &Song) -> Ordering {
     }
 }
 
+// Directory listing order is filesystem dependent, sort first so a seed
+// always produces the same shelf for the same collection.
+fn shuffle_albums(albums: &mut [Album], rng: &mut StdRng) {
+    albums.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.path.cmp(&b.path)));
+    albums.shuffle(rng);
+}
+
+// Days since the epoch, so the seed changes once a day at UTC midnight.
+fn daily_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or(0)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
-    let mut albums = load_albums(&args.datadir)?;
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid config: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(Cmd::Ctl { socket, command }) = args.command {
+        let socket = socket.or(config.control.socket).unwrap_or_else(ipc::default_socket);
+        if let Err(e) = ctl(&socket, command) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Cmd::Scrobble { command }) = args.command {
+        if let Err(e) = scrobble_command(&config.scrobble, command) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let datadir = args.datadir.expect("clap requires --datadir without a subcommand");
+    let mut albums = load_albums(&datadir)?;
     if albums.is_empty() {
-        eprintln!("No albums found in {}", args.datadir.display());
+        eprintln!("No albums found in {}", datadir.display());
         return Ok(());
     }
-    let mut rng = rand::thread_rng();
-    albums.shuffle(&mut rng);
-    let mut app = App::new(albums);
+    let seed = match (args.seed, args.daily) {
+        (Some(seed), _) => seed,
+        (None, true) => daily_seed(),
+        (None, false) => rand::random(),
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    shuffle_albums(&mut albums, &mut rng);
+    let mut app = match App::new(albums, &datadir, &config, seed, rng) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("Invalid config: {}", e);
+            std::process::exit(2);
+        }
+    };
+    app.echo_errors = args.headless;
     let (_stream, stream_handle) = OutputStream::try_default()?;
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-    let tick_rate = Duration::from_millis(250);
+    // The first signal asks the loop to quit cleanly, a second one while
+    // that is stuck kills the process.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGHUP, SIGINT] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+    let remote = Remote::new();
+    // Kept alive for as long as the service should stay on the bus.
+    let _mpris = if config.mpris.enabled {
+        mpris::spawn(remote.handle()).map_err(|e| app.log_error(format!("MPRIS: {}", e))).ok()
+    } else {
+        None
+    };
+    // Removes the socket file again when dropped.
+    let _socket = if config.control.enabled {
+        let path = config.control.socket.clone().unwrap_or_else(ipc::default_socket);
+        ipc::spawn(&path, remote.handle()).map_err(|e| app.log_error(format!("Control socket: {}", e))).ok()
+    } else {
+        None
+    };
+    if config.http.enabled {
+        if let Err(e) = http::spawn(&config.http.address, remote.handle()) {
+            app.log_error(format!("HTTP server: {}", e));
+        }
+    }
+    if config.mpd.enabled {
+        if let Err(e) = mpd::spawn(&config.mpd.address, &datadir, remote.handle()) {
+            app.log_error(format!("MPD server: {}", e));
+        }
+    }
+    if config.scrobble.enabled {
+        match Scrobbler::spawn(&config.scrobble) {
+            Ok(scrobbler) => app.scrobbler = Some(scrobbler),
+            Err(e) => app.log_error(format!("Scrobbling: {}", e)),
+        }
+    }
+    if config.notifications.enabled {
+        match Notifier::spawn(&config.notifications) {
+            Ok(notifier) => app.notifier = Some(notifier),
+            Err(e) => app.log_error(format!("Notifications: {}", e)),
+        }
+    }
+    let result = if args.headless {
+        if !(config.control.enabled || config.mpris.enabled || config.mpd.enabled || config.http.enabled) {
+            eprintln!("Warning: running headless with every remote interface turned off, only a signal will stop it");
+        }
+        run_headless(&mut app, &stream_handle, &remote, &shutdown)
+    } else {
+        install_panic_hook();
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        if !args.no_mouse {
+            execute!(stdout, EnableMouseCapture)?;
+        }
+        let backend = CrosstermBackend::new(stdout);
+        let result = Terminal::new(backend).map_err(Into::into).and_then(|mut terminal| {
+            run(&mut terminal, &mut app, &stream_handle, &remote, &shutdown)
+        });
+        restore_terminal();
+        result
+    };
+    if let Some(sink) = app.current_sink.take() {
+        sink.stop();
+    }
+    if let Some(listen) = app.listening.finish() {
+        app.record_listen(listen);
+    }
+    // Overlays shouldn't claim something is playing after levari is gone.
+    if let Some(files) = app.now_playing_files.as_mut() {
+        files.update(None);
+    }
+    result
+}
+
+// `levari ctl`: one command to the running player, status is printed as JSON.
+fn ctl(socket: &Path, command: CtlCommand) -> Result<(), Box<dyn Error>> {
+    let command = match command {
+        CtlCommand::Play => ipc::Command::Play,
+        CtlCommand::Pause => ipc::Command::Pause,
+        CtlCommand::Toggle => ipc::Command::PlayPause,
+        CtlCommand::Stop => ipc::Command::Stop,
+        CtlCommand::Next => ipc::Command::Next,
+        CtlCommand::Previous => ipc::Command::Previous,
+        CtlCommand::Skip { track } => ipc::Command::Skip { track },
+        CtlCommand::Seek { time } => match command::parse_seek(&time) {
+            Some(Seek::To(seconds)) => ipc::Command::Seek { seconds, relative: false },
+            Some(Seek::Forward(seconds)) => ipc::Command::Seek { seconds, relative: true },
+            Some(Seek::Back(seconds)) => ipc::Command::Seek { seconds: -seconds, relative: true },
+            None => return Err(format!("Invalid time '{}', use [+|-][h:]m:ss or seconds", time).into()),
+        },
+        // The player runs elsewhere, so relative paths are resolved here.
+        CtlCommand::Insert { path } => ipc::Command::Insert { path: fs::canonicalize(&path).unwrap_or(path) },
+        CtlCommand::Volume { percent } => ipc::Command::Volume { percent },
+        CtlCommand::Rpm { rpm } => ipc::Command::Rpm { rpm },
+        CtlCommand::Bookmark { path } => {
+            ipc::Command::Bookmark { path: path.map(|p| fs::canonicalize(&p).unwrap_or(p)) }
+        }
+        CtlCommand::Status => ipc::Command::Status,
+    };
+    let response = ipc::send(socket, &command)?;
+    if !response.ok {
+        return Err(response.error.unwrap_or_else(|| "levari refused the command".to_string()).into());
+    }
+    if let Some(status) = response.status {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    }
+    Ok(())
+}
+
+// `levari scrobble`: works on the queue file directly, levari doesn't need
+// to be running.
+fn scrobble_command(config: &config::ScrobbleConfig, command: ScrobbleCommand) -> Result<(), Box<dyn Error>> {
+    let path = config.queue_file().ok_or("No queue_file set and no $HOME to put one in")?;
+    let queue = scrobble::Queue::new(path);
+    let scrobbles = queue.load().map_err(|e| format!("{}: {}", queue.path().display(), e))?;
+    match command {
+        ScrobbleCommand::Queue => {
+            for s in &scrobbles {
+                println!("{}  {} - {} ({})", history::format_local(s.listened_at), s.artist, s.track, s.album);
+            }
+            println!("{} scrobbles waiting in {}", scrobbles.len(), queue.path().display());
+        }
+        ScrobbleCommand::Submit if scrobbles.is_empty() => println!("Nothing to submit"),
+        ScrobbleCommand::Submit => {
+            let submitted = scrobble::flush(&queue, &scrobble::Client::new(config))?;
+            println!("Submitted {} scrobbles to {}", submitted, config.url);
+        }
+    }
+    Ok(())
+}
+
+fn run<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    stream_handle: &OutputStreamHandle,
+    remote: &Remote,
+    shutdown: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    // Input is polled in short slices so remote requests don't wait a whole tick.
+    let remote_rate = Duration::from_millis(50);
     let mut last_tick = Instant::now();
-    loop {
-        terminal.draw(|f| ui(f, &mut app))?;
-        let timeout = tick_rate
+    while !shutdown.load(atomic::Ordering::Relaxed) && !app.quit_requested {
+        terminal.draw(|f| ui(f, app))?;
+        let timeout = TICK_RATE
             .checked_sub(last_tick.elapsed())
-            .unwrap_or_else(|| Duration::from_secs(0));
-        if event::poll(timeout)? {
-            if let CEvent::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => break,
-                    KeyCode::Char('n') => {
-                        if key.modifiers.contains(KeyModifiers::CONTROL)
-                            || key.modifiers.contains(KeyModifiers::SHIFT)
-                        {
-                            app.prev_bookmark();
-                        } else {
-                            app.next_bookmark();
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('N') => {
-                        app.prev_bookmark();
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char(c) if c.is_ascii_uppercase() && c != 'N' => {
-                        app.handle_shift_key(c);
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('j') => {
-                        match app.focus {
-                            Focus::Vinyl => app.set_focus(Focus::Albums),
-                            Focus::Albums => app.next_album(),
-                            Focus::SongList => app.next_song(),
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('k') => {
-                        match app.focus {
-                            Focus::Albums => app.previous_album(),
-                            Focus::SongList => app.previous_song(),
-                            _ => {}
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('h') => {
-                        if app.focus == Focus::SongList {
-                            app.set_focus(Focus::Albums);
-                        } else if app.focus == Focus::Vinyl {
-                            app.eject_current_album();
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('l') => {
-                        if app.focus == Focus::Albums {
-                            app.set_focus(Focus::SongList);
-                            let song_idx = if app.playing_album == Some(app.selected_index) {
-                                app.current_song_index
-                            } else {
-                                0
-                            };
-                            app.song_list_state.select(Some(song_idx));
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char(' ') => {
-                        if let Err(e) = app.space_action(&stream_handle) {
-                            eprintln!("Error: {}", e);
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Enter => {
-                        match app.focus {
-                            Focus::Albums | Focus::Vinyl => {
-                                if app.playing_album == Some(app.selected_index) {
-                                    app.eject_current_album();
-                                } else {
-                                    if let Err(e) = app.insert_album(&stream_handle) {
-                                        eprintln!("Error inserting album: {}", e);
-                                    }
-                                }
-                            }
-                            Focus::SongList => {
-                                if let Err(e) = app.skip_to_song(&stream_handle) {
-                                    eprintln!("Error skipping to song: {}", e);
-                                }
-                            }
-                        }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('+') | KeyCode::Char('=') => {
-                        app.increase_volume();
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('-') => {
-                        app.decrease_volume();
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('>') => {
-                        app.increase_speed(&stream_handle);
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('<') => {
-                        app.decrease_speed(&stream_handle);
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('m') => {
-                        app.toggle_bookmark();
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('p') => {
-                        app.jump_to_playing_album();
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('g') => {
-                        if app.focus == Focus::Albums {
-                            if !app.pending_g {
-                                app.pending_g = true;
-                            } else {
-                                app.go_to_top_album();
-                                app.pending_g = false;
-                            }
-                        } else {
-                            app.pending_g = false;
-                        }
-                    }
-                    KeyCode::Char('G') => {
-                        app.pending_g = false;
+            .unwrap_or_else(|| Duration::from_secs(0))
+            .min(remote_rate);
+        let ready = match event::poll(timeout) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => false,
+            ready => ready?,
+        };
+        if ready {
+            match event::read()? {
+                CEvent::Mouse(mouse) => {
+                    if let Err(e) = app.handle_mouse(mouse, stream_handle) {
+                        app.report(e);
                     }
-                    KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        if app.focus == Focus::Albums {
-                            app.half_page_down_album();
+                }
+                CEvent::Key(key) if app.command_line.is_some() => match app.command_key(key, stream_handle) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => app.report(e),
+                },
+                CEvent::Key(key) => {
+                    if let Some(invocation) = app.feed_key(Key::from(key)) {
+                        if invocation.action == Action::Quit && app.overlay.is_none() {
+                            break;
                         }
-                        app.pending_g = false;
-                    }
-                    KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        if app.focus == Focus::Albums {
-                            app.half_page_up_album();
+                        if let Err(e) = app.perform(invocation, stream_handle) {
+                            app.report(e);
                         }
-                        app.pending_g = false;
-                    }
-                    _ => {
-                        app.pending_g = false;
                     }
                 }
+                _ => {}
             }
         }
-        if last_tick.elapsed() >= tick_rate {
-            app.on_tick();
-            last_tick = Instant::now();
+        while let Some((request, reply)) = remote.try_recv() {
+            answer(app, stream_handle, remote, request, reply);
         }
+        step(app, remote, &mut last_tick);
     }
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
     Ok(())
 }
+
+// `--headless`: the same player without a terminal. Nothing to poll but the
+// remotes, so their requests are waited for between ticks. Signals are
+// noticed within a tick.
+fn run_headless(
+    app: &mut App,
+    stream_handle: &OutputStreamHandle,
+    remote: &Remote,
+    shutdown: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    let mut last_tick = Instant::now();
+    while !shutdown.load(atomic::Ordering::Relaxed) && !app.quit_requested {
+        if let Some((request, reply)) = remote.recv_timeout(TICK_RATE.saturating_sub(last_tick.elapsed())) {
+            answer(app, stream_handle, remote, request, reply);
+        }
+        step(app, remote, &mut last_tick);
+    }
+    Ok(())
+}
+
+fn answer(app: &mut App, stream_handle: &OutputStreamHandle, remote: &Remote, request: Request, reply: Sender<Reply>) {
+    let result = app.handle_request(request, stream_handle);
+    // So a status asked for right after the reply already shows the change.
+    remote.publish(app.snapshot());
+    let _ = reply.send(result);
+}
+
+// Ticks when it is time and tells the remotes where things are at.
+fn step(app: &mut App, remote: &Remote, last_tick: &mut Instant) {
+    if last_tick.elapsed() >= TICK_RATE {
+        app.on_tick();
+        *last_tick = Instant::now();
+    }
+    remote.publish(app.snapshot());
+    if std::mem::take(&mut app.shelf_changed) {
+        remote.publish_shelf(app.shelf_snapshot());
+    }
+}
+
+// Best effort, this also runs from the panic hook where errors can't go anywhere.
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture, cursor::Show);
+}
+
+// Restores the terminal before the default hook prints the message, which
+// would otherwise be drawn on the alternate screen and lost with it. Only
+// a panic on the main thread ends the interface; when a front end or hook
+// thread panics, the interface keeps running and needs the terminal as is.
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            restore_terminal();
+        }
+        default_hook(info);
+    }));
+}
@@ -0,0 +1,175 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+
+// Extensions of text files shown as liner notes in the detail view.
+const NOTE_EXTENSIONS: &[&str] = &["txt", "nfo", "log", "md"];
+// Anything bigger is cut, rip logs of box sets can be huge.
+const MAX_NOTE_BYTES: usize = 256 * 1024;
+
+#[derive(Default, Clone, Debug)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub date: Option<String>,
+    pub genre: Option<String>,
+    pub label: Option<String>,
+    /// Unsynced or LRC formatted, from ID3 USLT or a LYRICS comment
+    pub lyrics: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackInfo {
+    pub tags: Tags,
+    /// Container, taken from the file extension
+    pub format: String,
+    pub sample_rate: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+    pub channels: Option<usize>,
+    /// Seconds, when the container states it
+    pub duration: Option<f64>,
+    /// Bytes
+    pub size: u64,
+}
+
+// Everything the detail view shows about an album, read when it is opened.
+pub struct AlbumDetails {
+    /// One entry per song, in shelf order
+    pub tracks: Vec<Result<TrackInfo, String>>,
+    /// File name and contents of each text file in the album directory
+    pub notes: Vec<(String, String)>,
+}
+
+impl AlbumDetails {
+    pub fn load<'a>(dir: &Path, songs: impl Iterator<Item = &'a Path>) -> Self {
+        let tracks = songs.map(|path| probe(path).map_err(|e| e.to_string())).collect();
+        Self { tracks, notes: liner_notes(dir) }
+    }
+
+    // Album level tags come from the first track that has them.
+    pub fn tags(&self) -> Tags {
+        let infos: Vec<&TrackInfo> = self.tracks.iter().filter_map(|t| t.as_ref().ok()).collect();
+        let first = |get: fn(&Tags) -> &Option<String>| infos.iter().find_map(|t| get(&t.tags).clone());
+        Tags {
+            artist: first(|t| &t.artist),
+            album_artist: first(|t| &t.album_artist),
+            album: first(|t| &t.album),
+            date: first(|t| &t.date),
+            genre: first(|t| &t.genre),
+            label: first(|t| &t.label),
+            ..Tags::default()
+        }
+    }
+}
+
+// Reads the container headers only, nothing is decoded.
+pub fn probe(path: &Path) -> Result<TrackInfo, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hint = Hint::new();
+    let format = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_string();
+    hint.with_extension(&format);
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    // ID3 tags sit in front of the container, Vorbis comments inside it.
+    let mut tags = Tags::default();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        read_tags(revision, &mut tags);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        read_tags(revision, &mut tags);
+    }
+
+    let params = probed.format.default_track().map(|t| t.codec_params.clone()).unwrap_or_default();
+    let duration = match (params.time_base, params.n_frames) {
+        (Some(base), Some(frames)) => {
+            let time = base.calc_time(frames);
+            Some(time.seconds as f64 + time.frac)
+        }
+        _ => None,
+    };
+    Ok(TrackInfo {
+        tags,
+        format: format.to_uppercase(),
+        sample_rate: params.sample_rate,
+        bits_per_sample: params.bits_per_sample,
+        channels: params.channels.map(|c| c.count()),
+        duration,
+        size,
+    })
+}
+
+fn read_tags(revision: &MetadataRevision, tags: &mut Tags) {
+    for tag in revision.tags() {
+        let field = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => &mut tags.title,
+            Some(StandardTagKey::Artist) => &mut tags.artist,
+            Some(StandardTagKey::AlbumArtist) => &mut tags.album_artist,
+            Some(StandardTagKey::Album) => &mut tags.album,
+            Some(StandardTagKey::Date) | Some(StandardTagKey::OriginalDate) => &mut tags.date,
+            Some(StandardTagKey::Genre) => &mut tags.genre,
+            Some(StandardTagKey::Label) => &mut tags.label,
+            Some(StandardTagKey::Lyrics) => &mut tags.lyrics,
+            _ => continue,
+        };
+        // RIFF INFO strings keep their NUL terminators.
+        if field.is_none() {
+            *field = Some(tag.value.to_string().trim_end_matches('\0').to_string());
+        }
+    }
+}
+
+// Text files next to the music: notes.txt, info.nfo, rip logs and the like.
+pub fn liner_notes(dir: &Path) -> Vec<(String, String)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut notes: Vec<(String, String)> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+            path.is_file() && NOTE_EXTENSIONS.contains(&ext.as_str())
+        })
+        .filter_map(|path| {
+            let mut bytes = Vec::new();
+            File::open(&path).ok()?.take(MAX_NOTE_BYTES as u64).read_to_end(&mut bytes).ok()?;
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            Some((name, decode_text(&bytes)))
+        })
+        .collect();
+    notes.sort_by_key(|(name, _)| name.to_lowercase());
+    notes
+}
+
+// Rip logs from Windows tools are often UTF-16 with a byte order mark.
+fn decode_text(bytes: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| from([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    let text = match bytes {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    };
+    text.replace("\r\n", "\n").replace('\t', "    ")
+}
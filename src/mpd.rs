@@ -0,0 +1,764 @@
+use std::{
+    error::Error,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::remote::{AlbumSnapshot, Handle, Request, Snapshot, Status};
+
+// What clients are told in the greeting. Only the commands in `COMMANDS`
+// are understood, the rest are answered with an ACK.
+const PROTOCOL_VERSION: &str = "0.23.0";
+// How often the snapshot is checked for changes to wake idling clients.
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+// Position drift beyond this is reported as a seek.
+const SEEK_TOLERANCE: f64 = 1.5;
+// Clients stay connected while they idle, so rather than a pool of workers
+// there is a cap on how many are served at once, two threads each.
+const MAX_CLIENTS: usize = 8;
+
+// ACK codes, as MPD numbers them.
+const ACK_ARG: u32 = 2;
+const ACK_UNKNOWN: u32 = 5;
+const ACK_NO_EXIST: u32 = 50;
+const ACK_SYSTEM: u32 = 52;
+
+// Subsystems levari reports changes for, in the order of `Changes::last`.
+const SUBSYSTEMS: [&str; 4] = ["database", "playlist", "player", "mixer"];
+// The rest are accepted by `idle` but never fire.
+const QUIET_SUBSYSTEMS: &[&str] =
+    &["update", "stored_playlist", "options", "output", "sticker", "subscription", "message", "partition"];
+
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "commands",
+    "consume",
+    "currentsong",
+    "decoders",
+    "getvol",
+    "idle",
+    "list",
+    "listall",
+    "listallinfo",
+    "listplaylists",
+    "lsinfo",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "replay_gain_status",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+    "volume",
+];
+
+// Bumped by the watcher. Clients remember which serial they last reported
+// per subsystem and are woken for anything newer.
+#[derive(Default)]
+struct Changes {
+    serial: u64,
+    /// Serial of the last change, per entry of `SUBSYSTEMS`
+    last: [u64; 4],
+    /// MPD's playlist version, the queue changes whenever an album goes in
+    playlist_version: u32,
+}
+
+#[derive(Default)]
+struct Events {
+    changes: Mutex<Changes>,
+    changed: Condvar,
+}
+
+impl Events {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Changes> {
+        self.changes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// A failed command, answered as `ACK [code@index] {command} message`.
+struct Ack(u32, String);
+
+// One of the `MAX_CLIENTS` places, given back when the session ends.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Serves the MPD protocol on `address`. The shelf is a flat list of album
+// directories below `music_dir` and the queue is always the inserted album:
+// adding anything inserts the album it belongs to, clearing ejects it.
+// Returns the address listened on.
+pub fn spawn(address: &str, music_dir: &Path, remote: Handle) -> Result<SocketAddr, Box<dyn Error>> {
+    let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+    let bound = listener.local_addr()?;
+    let events = Arc::new(Events::default());
+    let watched = Arc::clone(&events);
+    let watcher = remote.clone();
+    thread::Builder::new().name("mpd-watch".into()).spawn(move || watch(watcher, watched))?;
+    let music_dir = music_dir.to_path_buf();
+    let started = Instant::now();
+    let clients = Arc::new(AtomicUsize::new(0));
+    thread::Builder::new().name("mpd".into()).spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            // Only this thread takes places, so the check can't race.
+            if clients.load(Ordering::SeqCst) >= MAX_CLIENTS {
+                let _ = stream.write_all(ack(0, "", ACK_SYSTEM, "Too many clients").as_bytes());
+                continue;
+            }
+            clients.fetch_add(1, Ordering::SeqCst);
+            let slot = Slot(Arc::clone(&clients));
+            let session = Session {
+                remote: remote.clone(),
+                events: Arc::clone(&events),
+                music_dir: music_dir.clone(),
+                started,
+                seen: events.lock().last,
+            };
+            let _ = thread::Builder::new().name("mpd-client".into()).spawn(move || {
+                let _slot = slot;
+                session.serve(stream)
+            });
+        }
+    })?;
+    Ok(bound)
+}
+
+fn watch(remote: Handle, events: Arc<Events>) {
+    let mut last = remote.snapshot();
+    let mut shelf = remote.shelf();
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let now = remote.snapshot();
+        let now_shelf = remote.shelf();
+        let album = |s: &Snapshot| s.album.as_ref().map(|a| a.path.clone());
+        let expected = match last.status {
+            Status::Playing => last.position + WATCH_INTERVAL.as_secs_f64() * last.rpm as f64 / 33.0,
+            _ => last.position,
+        };
+        let changed = [
+            !Arc::ptr_eq(&shelf, &now_shelf),
+            album(&now) != album(&last),
+            now.status != last.status || now.track != last.track || (now.position - expected).abs() > SEEK_TOLERANCE,
+            now.volume != last.volume,
+        ];
+        if changed.contains(&true) {
+            let mut changes = events.lock();
+            changes.serial += 1;
+            let serial = changes.serial;
+            for (last, _) in changes.last.iter_mut().zip(changed).filter(|(_, changed)| *changed) {
+                *last = serial;
+            }
+            if changed[1] {
+                changes.playlist_version += 1;
+            }
+            events.changed.notify_all();
+        }
+        last = now;
+        shelf = now_shelf;
+    }
+}
+
+struct Session {
+    remote: Handle,
+    events: Arc<Events>,
+    music_dir: PathBuf,
+    started: Instant,
+    /// Serial of the last change reported by `idle`, per subsystem
+    seen: [u64; 4],
+}
+
+impl Session {
+    fn serve(mut self, stream: TcpStream) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+        // Lines are read on their own thread so `idle` can wait for changes
+        // and for `noidle` at the same time.
+        let (sender, lines) = mpsc::channel();
+        let reader = thread::Builder::new().name("mpd-read".into()).spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        if reader.is_err() || writer.write_all(format!("OK MPD {}\n", PROTOCOL_VERSION).as_bytes()).is_err() {
+            return;
+        }
+        let mut pending = None;
+        loop {
+            let Some(line) = pending.take().or_else(|| lines.recv().ok()) else {
+                return;
+            };
+            let line = line.trim().to_string();
+            let out = match line.as_str() {
+                "" | "noidle" => continue,
+                "close" => return,
+                "command_list_begin" | "command_list_ok_begin" => {
+                    let mut list = Vec::new();
+                    loop {
+                        match lines.recv() {
+                            Ok(next) if next.trim() == "command_list_end" => break,
+                            Ok(next) => list.push(next),
+                            Err(_) => return,
+                        }
+                    }
+                    self.run_list(&list, line == "command_list_ok_begin")
+                }
+                idle if idle.split_whitespace().next() == Some("idle") => {
+                    let wanted: Vec<String> = idle.split_whitespace().skip(1).map(str::to_lowercase).collect();
+                    let known = |w: &str| SUBSYSTEMS.contains(&w) || QUIET_SUBSYSTEMS.contains(&w);
+                    match wanted.iter().find(|w| !known(w)) {
+                        Some(unknown) => ack(0, "idle", ACK_ARG, &format!("Unrecognized idle event: {}", unknown)),
+                        None => match self.idle(&wanted, &lines) {
+                            Some((changed, interrupted)) => {
+                                pending = interrupted;
+                                changed.iter().map(|s| format!("changed: {}\n", s)).collect::<String>() + "OK\n"
+                            }
+                            None => return,
+                        },
+                    }
+                }
+                _ => self.run_list(&[line], false),
+            };
+            if writer.write_all(out.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    // Runs commands until one fails. A single command is a list of one.
+    fn run_list(&mut self, list: &[String], ok_each: bool) -> String {
+        let mut out = String::new();
+        for (i, line) in list.iter().enumerate() {
+            let args = match tokenize(line) {
+                Ok(args) if !args.is_empty() => args,
+                Ok(_) => return out + &ack(i, "", ACK_UNKNOWN, "No command given"),
+                Err(e) => return out + &ack(i, "", ACK_ARG, &e),
+            };
+            let name = args[0].to_lowercase();
+            match self.execute(&name, &args[1..]) {
+                Ok(body) => out += &body,
+                Err(Ack(code, message)) => return out + &ack(i, &name, code, &message),
+            }
+            if ok_each {
+                out += "list_OK\n";
+            }
+        }
+        out + "OK\n"
+    }
+
+    // Waits for a change to one of `wanted`, or any subsystem if empty.
+    // Returns what changed and a command that cut the wait short, if it
+    // wasn't `noidle`, or nothing once the client is gone.
+    fn idle(&mut self, wanted: &[String], lines: &Receiver<String>) -> Option<(Vec<&'static str>, Option<String>)> {
+        let mut changes = self.events.lock();
+        loop {
+            let changed: Vec<usize> = (0..SUBSYSTEMS.len())
+                .filter(|&i| changes.last[i] > self.seen[i])
+                .filter(|&i| wanted.is_empty() || wanted.iter().any(|w| w == SUBSYSTEMS[i]))
+                .collect();
+            if !changed.is_empty() {
+                for &i in &changed {
+                    self.seen[i] = changes.last[i];
+                }
+                return Some((changed.into_iter().map(|i| SUBSYSTEMS[i]).collect(), None));
+            }
+            let woken = self.events.changed.wait_timeout(changes, WATCH_INTERVAL);
+            changes = woken.unwrap_or_else(PoisonError::into_inner).0;
+            match lines.try_recv() {
+                Ok(line) if line.trim() == "noidle" => return Some((Vec::new(), None)),
+                Ok(line) => return Some((Vec::new(), Some(line))),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+    }
+
+    fn execute(&mut self, name: &str, args: &[String]) -> Result<String, Ack> {
+        let snapshot = self.remote.snapshot();
+        let mut out = String::new();
+        match name {
+            "ping" | "decoders" | "urlhandlers" | "listplaylists" => {}
+            "commands" => COMMANDS.iter().for_each(|c| line(&mut out, "command", c)),
+            "notcommands" => {}
+            "tagtypes" if args.is_empty() => {
+                ["Album", "Title", "Track"].iter().for_each(|t| line(&mut out, "tagtype", t))
+            }
+            // Clients narrow the tags down, levari only has these three anyway.
+            "tagtypes" => {}
+            "outputs" => out += "outputid: 0\noutputname: levari\nplugin: rodio\noutputenabled: 1\n",
+            "replay_gain_status" => out += "replay_gain_mode: off\n",
+            // The needle goes from the first track to the last, nothing else.
+            "random" | "repeat" | "single" | "consume" => match args.first().map(String::as_str) {
+                Some("0") => {}
+                Some("1") | Some("oneshot") => return Err(Ack(ACK_ARG, "Albums are played in order".to_string())),
+                _ => return Err(Ack(ACK_ARG, format!("Usage: {} 0", name))),
+            },
+            "status" => self.status(&mut out, &snapshot),
+            "stats" => self.stats(&mut out),
+            "currentsong" => {
+                if let (Some(album), Some(track)) = (&snapshot.album, snapshot.track) {
+                    self.song(&mut out, album, track, true);
+                }
+            }
+            "getvol" => line(&mut out, "volume", percent(&snapshot)),
+            "playlistinfo" | "playlistid" | "plchanges" | "plchangesposid" => {
+                let Some(album) = &snapshot.album else {
+                    return Ok(out);
+                };
+                let positions: Vec<usize> = match (name, args.first()) {
+                    ("playlistinfo", Some(_)) => vec![parse::<usize>(args, 0)?],
+                    ("playlistid", Some(_)) => vec![parse::<usize>(args, 0)?.wrapping_sub(1)],
+                    ("plchanges" | "plchangesposid", _) => {
+                        // Anything older than the current album gets all of it.
+                        if parse::<u32>(args, 0)? == self.events.lock().playlist_version {
+                            Vec::new()
+                        } else {
+                            (0..album.tracks.len()).collect()
+                        }
+                    }
+                    _ => (0..album.tracks.len()).collect(),
+                };
+                for pos in positions {
+                    if pos >= album.tracks.len() {
+                        return Err(Ack(ACK_ARG, "Bad song index".to_string()));
+                    }
+                    if name == "plchangesposid" {
+                        writeln!(out, "cpos: {}\nId: {}", pos, pos + 1).ok();
+                    } else {
+                        self.song(&mut out, album, pos, true);
+                    }
+                }
+            }
+            "lsinfo" | "listall" | "listallinfo" => {
+                let uri = args.first().map(|a| a.trim_matches('/')).unwrap_or_default();
+                let shelf = self.remote.shelf();
+                if uri.is_empty() {
+                    for album in shelf.iter() {
+                        line(&mut out, "directory", self.album_uri(album));
+                        if name != "lsinfo" {
+                            self.songs(&mut out, album, name == "listallinfo");
+                        }
+                    }
+                    return Ok(out);
+                }
+                match self.find(&shelf, uri) {
+                    Some((album, None)) => self.songs(&mut out, album, name != "listall"),
+                    Some((album, Some(track))) if name == "listall" => {
+                        line(&mut out, "file", self.track_uri(album, track))
+                    }
+                    Some((album, Some(track))) => self.song(&mut out, album, track, false),
+                    None => return Err(Ack(ACK_NO_EXIST, "No such directory".to_string())),
+                }
+            }
+            "list" => match args.first().map(|a| a.to_lowercase()).as_deref() {
+                Some("album") => self.remote.shelf().iter().for_each(|a| line(&mut out, "Album", &a.name)),
+                Some(_) => {}
+                None => return Err(Ack(ACK_ARG, "Usage: list album".to_string())),
+            },
+            "play" | "playid" => match args.first() {
+                None => self.send(Request::Play)?,
+                Some(_) => {
+                    let n = parse::<usize>(args, 0)?;
+                    let pos = if name == "playid" { n.wrapping_sub(1) } else { n };
+                    if snapshot.album.as_ref().is_none_or(|a| pos >= a.tracks.len()) {
+                        return Err(Ack(ACK_ARG, "Bad song index".to_string()));
+                    }
+                    self.send(Request::SkipTo(pos))?;
+                    self.send(Request::Play)?;
+                }
+            },
+            "pause" => match args.first().map(String::as_str) {
+                None => self.send(Request::PlayPause)?,
+                Some("1") => self.send(Request::Pause)?,
+                Some("0") => self.send(Request::Play)?,
+                Some(_) => return Err(Ack(ACK_ARG, "Usage: pause [0|1]".to_string())),
+            },
+            "stop" | "clear" => self.send(Request::Stop)?,
+            "next" => self.send(Request::Next)?,
+            "previous" => self.send(Request::Previous)?,
+            "seek" | "seekid" => {
+                let n = parse::<usize>(args, 0)?;
+                let pos = if name == "seekid" { n.wrapping_sub(1) } else { n };
+                let time = parse::<f64>(args, 1)?;
+                let Some(album) = snapshot.album.as_ref().filter(|a| pos < a.tracks.len()) else {
+                    return Err(Ack(ACK_ARG, "Bad song index".to_string()));
+                };
+                let start: u64 = album.tracks[..pos].iter().map(|t| t.duration).sum();
+                self.send(Request::SeekAlbum(start as f64 + time.max(0.0)))?;
+            }
+            "seekcur" => {
+                let time = args.first().ok_or_else(|| Ack(ACK_ARG, "Usage: seekcur [+|-]TIME".to_string()))?;
+                let seconds = parse::<f64>(args, 0)?;
+                match snapshot.track {
+                    _ if time.starts_with(['+', '-']) => self.send(Request::Seek(seconds))?,
+                    Some(track) => self.send(Request::SetPosition(track, seconds.max(0.0)))?,
+                    None => return Err(Ack(ACK_ARG, "Not playing".to_string())),
+                }
+            }
+            "setvol" | "volume" => {
+                let value = parse::<i32>(args, 0)?;
+                let target = if name == "volume" { percent(&snapshot) as i32 + value } else { value };
+                if name == "setvol" && !(0..=100).contains(&value) {
+                    return Err(Ack(ACK_ARG, "Volume out of range".to_string()));
+                }
+                self.send(Request::Volume(target.clamp(0, 100) as f32 / 100.0))?;
+            }
+            "add" | "addid" => {
+                let uri = args.first().map(|a| a.trim_matches('/')).unwrap_or_default();
+                let shelf = self.remote.shelf();
+                let Some((album, track)) = self.find(&shelf, uri) else {
+                    return Err(Ack(ACK_NO_EXIST, "No such album on the shelf".to_string()));
+                };
+                self.send(Request::Insert(album.path.clone()))?;
+                if let Some(track) = track {
+                    self.send(Request::SkipTo(track))?;
+                }
+                if name == "addid" {
+                    line(&mut out, "Id", track.unwrap_or(0) + 1);
+                }
+            }
+            _ => return Err(Ack(ACK_UNKNOWN, format!("unknown command \"{}\"", name))),
+        }
+        Ok(out)
+    }
+
+    fn send(&self, request: Request) -> Result<(), Ack> {
+        self.remote.send(request).map_err(|e| Ack(ACK_SYSTEM, e))
+    }
+
+    fn status(&self, out: &mut String, snapshot: &Snapshot) {
+        let changes = self.events.lock();
+        line(out, "volume", percent(snapshot));
+        out.push_str("repeat: 0\nrandom: 0\nsingle: 0\nconsume: 0\n");
+        line(out, "playlist", changes.playlist_version);
+        line(out, "playlistlength", snapshot.album.as_ref().map_or(0, |a| a.tracks.len()));
+        let state = match snapshot.status {
+            Status::Playing => "play",
+            Status::Paused => "pause",
+            Status::Stopped => "stop",
+        };
+        line(out, "state", state);
+        let (Some(album), Some(track)) = (&snapshot.album, snapshot.track) else {
+            return;
+        };
+        let duration = album.tracks[track].duration;
+        line(out, "song", track);
+        line(out, "songid", track + 1);
+        writeln!(out, "time: {}:{}", snapshot.position as u64, duration).ok();
+        writeln!(out, "elapsed: {:.3}", snapshot.position).ok();
+        line(out, "duration", duration);
+        if let Some(next) = (track + 1..album.tracks.len()).find(|&i| album.tracks[i].playable) {
+            line(out, "nextsong", next);
+            line(out, "nextsongid", next + 1);
+        }
+    }
+
+    fn stats(&self, out: &mut String) {
+        let shelf = self.remote.shelf();
+        line(out, "artists", 0);
+        line(out, "albums", shelf.len());
+        line(out, "songs", shelf.iter().map(|a| a.tracks.len()).sum::<usize>());
+        line(out, "uptime", self.started.elapsed().as_secs());
+        line(out, "playtime", self.started.elapsed().as_secs());
+        line(out, "db_playtime", shelf.iter().flat_map(|a| &a.tracks).map(|t| t.duration).sum::<u64>());
+    }
+
+    fn songs(&self, out: &mut String, album: &AlbumSnapshot, info: bool) {
+        for track in 0..album.tracks.len() {
+            if info {
+                self.song(out, album, track, false);
+            } else {
+                line(out, "file", self.track_uri(album, track));
+            }
+        }
+    }
+
+    // `queued` adds the position and id the track has in the queue.
+    fn song(&self, out: &mut String, album: &AlbumSnapshot, track: usize, queued: bool) {
+        let song = &album.tracks[track];
+        line(out, "file", self.track_uri(album, track));
+        line(out, "Title", &song.title);
+        line(out, "Album", &album.name);
+        line(out, "Track", track + 1);
+        line(out, "Time", song.duration);
+        line(out, "duration", song.duration);
+        if queued {
+            line(out, "Pos", track);
+            line(out, "Id", track + 1);
+        }
+    }
+
+    // Relative to the music directory, or just the name when the music
+    // directory is the album itself.
+    fn album_uri(&self, album: &AlbumSnapshot) -> String {
+        match album.path.strip_prefix(&self.music_dir) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.to_string_lossy().into_owned(),
+            _ => album.name.clone(),
+        }
+    }
+
+    fn track_uri(&self, album: &AlbumSnapshot, track: usize) -> String {
+        let file = album.tracks[track].path.file_name().unwrap_or_default().to_string_lossy();
+        format!("{}/{}", self.album_uri(album), file)
+    }
+
+    fn find<'a>(&self, shelf: &'a [AlbumSnapshot], uri: &str) -> Option<(&'a AlbumSnapshot, Option<usize>)> {
+        shelf.iter().find_map(|album| {
+            let album_uri = self.album_uri(album);
+            if uri == album_uri {
+                return Some((album, None));
+            }
+            let file = uri.strip_prefix(&album_uri)?.strip_prefix('/')?;
+            let track =
+                album.tracks.iter().position(|t| t.path.file_name().is_some_and(|n| n.to_string_lossy() == file))?;
+            Some((album, Some(track)))
+        })
+    }
+}
+
+fn line(out: &mut String, key: &str, value: impl std::fmt::Display) {
+    writeln!(out, "{}: {}", key, value).ok();
+}
+
+fn ack(index: usize, command: &str, code: u32, message: &str) -> String {
+    format!("ACK [{}@{}] {{{}}} {}\n", code, index, command, message)
+}
+
+// MPD's volume stops at 100, levari's at 200.
+fn percent(snapshot: &Snapshot) -> u32 {
+    ((snapshot.volume * 100.0).round() as u32).min(100)
+}
+
+fn parse<T: std::str::FromStr>(args: &[String], i: usize) -> Result<T, Ack> {
+    let arg = args.get(i).ok_or_else(|| Ack(ACK_ARG, "Missing argument".to_string()))?;
+    arg.parse().map_err(|_| Ack(ACK_ARG, format!("Invalid argument \"{}\"", arg)))
+}
+
+// Arguments are separated by spaces, and may be double quoted with
+// backslash escapes.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => arg.extend(chars.next()),
+                    Some(c) => arg.push(c),
+                    None => return Err("Missing closing '\"'".to_string()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::Shutdown};
+
+    use super::*;
+    use crate::remote::{Remote, TrackSnapshot};
+
+    // A server on a free port, with a stand-in main loop that accepts every
+    // request, publishes the volume it is asked for and passes the request
+    // on to the test.
+    fn start() -> (SocketAddr, Receiver<String>) {
+        let remote = Remote::new();
+        let track = |n: usize| TrackSnapshot {
+            title: format!("Track {}", n),
+            path: PathBuf::from(format!("/music/Album/{:02}.flac", n)),
+            duration: 100,
+            playable: true,
+        };
+        let album = AlbumSnapshot {
+            name: "Album".to_string(),
+            path: PathBuf::from("/music/Album"),
+            tracks: vec![track(1), track(2)],
+            ..Default::default()
+        };
+        remote.publish_shelf(vec![album]);
+        let address = spawn("127.0.0.1:0", Path::new("/music"), remote.handle()).unwrap();
+        let (seen, requests) = mpsc::channel();
+        thread::spawn(move || {
+            while let Some((request, reply)) = remote.recv_timeout(Duration::from_secs(10)) {
+                if let Request::Volume(volume) = request {
+                    remote.publish(Snapshot { volume, ..Default::default() });
+                }
+                let _ = seen.send(format!("{:?}", request));
+                let _ = reply.send(Ok(()));
+            }
+        });
+        (address, requests)
+    }
+
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn connect(address: SocketAddr) -> (Self, String) {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut client = Self { reader: BufReader::new(stream.try_clone().unwrap()), stream };
+            let mut greeting = String::new();
+            client.reader.read_line(&mut greeting).unwrap();
+            (client, greeting)
+        }
+
+        fn send(&mut self, lines: &str) {
+            self.stream.write_all(lines.as_bytes()).unwrap();
+        }
+
+        // Everything up to and including the closing OK or ACK line.
+        fn answer(&mut self) -> String {
+            let mut out = String::new();
+            loop {
+                let start = out.len();
+                if self.reader.read_line(&mut out).unwrap() == 0 {
+                    return out;
+                }
+                if out[start..] == *"OK\n" || out[start..].starts_with("ACK ") {
+                    return out;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arguments_split_on_spaces_and_quotes() {
+        assert_eq!(tokenize("  play   3 ").unwrap(), ["play", "3"]);
+        let quoted = tokenize(r#"add "Some Album/01 \"Intro\".flac""#).unwrap();
+        assert_eq!(quoted, ["add", r#"Some Album/01 "Intro".flac"#]);
+        assert_eq!(tokenize(r#"lsinfo "back\\slash" """#).unwrap(), ["lsinfo", r"back\slash", ""]);
+        assert!(tokenize("").unwrap().is_empty());
+        assert_eq!(tokenize(r#"add "Album"#).unwrap_err(), "Missing closing '\"'");
+    }
+
+    #[test]
+    fn errors_name_the_command_and_its_place_in_the_list() {
+        assert_eq!(ack(2, "play", ACK_ARG, "Bad song index"), "ACK [2@2] {play} Bad song index\n");
+        let (address, requests) = start();
+        let (mut client, greeting) = Client::connect(address);
+        assert_eq!(greeting, format!("OK MPD {}\n", PROTOCOL_VERSION));
+        client.send("play 9\n");
+        assert_eq!(client.answer(), "ACK [2@0] {play} Bad song index\n");
+        client.send("frobnicate\n");
+        assert_eq!(client.answer(), "ACK [5@0] {frobnicate} unknown command \"frobnicate\"\n");
+        client.send("add \"Album\n");
+        assert_eq!(client.answer(), "ACK [2@0] {} Missing closing '\"'\n");
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn command_lists() {
+        let (address, requests) = start();
+        let (mut client, _) = Client::connect(address);
+        client.send("command_list_begin\nping\nsetvol 40\ncommand_list_end\n");
+        assert_eq!(client.answer(), "OK\n");
+        assert_eq!(requests.recv_timeout(Duration::from_secs(5)).unwrap(), "Volume(0.4)");
+
+        client.send("command_list_ok_begin\nping\ngetvol\ncommand_list_end\n");
+        assert_eq!(client.answer(), "list_OK\nvolume: 40\nlist_OK\nOK\n");
+
+        // The list stops at the first failure, which is numbered from 0.
+        client.send("command_list_ok_begin\nping\nfrobnicate\nsetvol 50\ncommand_list_end\n");
+        assert_eq!(client.answer(), "list_OK\nACK [5@1] {frobnicate} unknown command \"frobnicate\"\n");
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn idle_waits_for_changes() {
+        let (address, _requests) = start();
+        let (mut idler, _) = Client::connect(address);
+        let (mut other, _) = Client::connect(address);
+
+        idler.send("idle bogus\n");
+        assert_eq!(idler.answer(), "ACK [2@0] {idle} Unrecognized idle event: bogus\n");
+
+        idler.send("idle\n");
+        idler.send("noidle\n");
+        assert_eq!(idler.answer(), "OK\n");
+
+        // Another command ends the wait too, and is answered after it.
+        idler.send("idle\nping\n");
+        assert_eq!(idler.answer(), "OK\n");
+        assert_eq!(idler.answer(), "OK\n");
+
+        idler.send("idle mixer player\n");
+        other.send("setvol 30\n");
+        assert_eq!(other.answer(), "OK\n");
+        assert_eq!(idler.answer(), "changed: mixer\nOK\n");
+    }
+
+    #[test]
+    fn clients_beyond_the_cap_are_turned_away() {
+        let (address, _requests) = start();
+        let mut clients: Vec<Client> = (0..MAX_CLIENTS).map(|_| Client::connect(address).0).collect();
+        let (mut refused, greeting) = Client::connect(address);
+        assert_eq!(greeting, "ACK [52@0] {} Too many clients\n");
+        assert_eq!(refused.reader.read(&mut [0]).unwrap(), 0);
+
+        clients.pop().unwrap().stream.shutdown(Shutdown::Both).unwrap();
+        for _ in 0..100 {
+            let (_client, greeting) = Client::connect(address);
+            if greeting.starts_with("OK MPD") {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("the place of a client that left was not given back");
+    }
+}
@@ -0,0 +1,416 @@
+use std::{collections::HashMap, error::Error, os::unix::ffi::OsStrExt, thread, time::Duration};
+
+use zbus::{
+    blocking::{connection, Connection},
+    fdo, interface,
+    object_server::SignalEmitter,
+    zvariant::{ObjectPath, OwnedValue, Value},
+};
+
+use crate::remote::{Handle, Request, Snapshot, Status};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.levari";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+// How often the snapshot is checked for changes to signal.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+// Position drift beyond this is reported as a seek.
+const SEEK_TOLERANCE: f64 = 1.5;
+
+// Registers on the session bus. The returned connection must be kept alive
+// for as long as the service should be up.
+pub fn spawn(remote: Handle) -> Result<Connection, Box<dyn Error>> {
+    serve(connection::Builder::session()?, remote)
+}
+
+fn serve(builder: connection::Builder<'_>, remote: Handle) -> Result<Connection, Box<dyn Error>> {
+    let conn = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root { remote: remote.clone() })?
+        .serve_at(OBJECT_PATH, Player { remote: remote.clone() })?
+        .build()?;
+    let watcher = conn.clone();
+    thread::Builder::new().name("mpris".into()).spawn(move || watch(watcher, remote))?;
+    Ok(conn)
+}
+
+struct Root {
+    remote: Handle,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) -> fdo::Result<()> {
+        self.remote.send(Request::Quit).map_err(fdo::Error::Failed)
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "Levari".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct Player {
+    remote: Handle,
+}
+
+impl Player {
+    fn send(&self, request: Request) -> fdo::Result<()> {
+        self.remote.send(request).map_err(fdo::Error::Failed)
+    }
+}
+
+// Rate is the RPM relative to 33, the speed records are cut for.
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) -> fdo::Result<()> {
+        self.send(Request::Next)
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        self.send(Request::Previous)
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.send(Request::Pause)
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.send(Request::PlayPause)
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        self.send(Request::Stop)
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.send(Request::Play)
+    }
+
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        self.send(Request::Seek(offset as f64 / 1e6))
+    }
+
+    // Ignored unless the track is still the one playing, as the spec asks.
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let snapshot = self.remote.snapshot();
+        match snapshot.track {
+            Some(track) if track_id.as_str() == track_id_of(&snapshot) && position >= 0 => {
+                self.send(Request::SetPosition(track, position as f64 / 1e6))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn open_uri(&self, _uri: String) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported("Albums are inserted from the shelf".to_string()))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        playback_status(&self.remote.snapshot()).to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        self.remote.snapshot().rpm as f64 / 33.0
+    }
+
+    // Snaps to the closest of the three speeds.
+    #[zbus(property)]
+    fn set_rate(&mut self, rate: f64) -> zbus::Result<()> {
+        let rpm = [33.0, 45.0, 78.0]
+            .into_iter()
+            .min_by(|a: &f32, b: &f32| (a / 33.0 - rate as f32).abs().total_cmp(&(b / 33.0 - rate as f32).abs()))
+            .unwrap_or(33.0);
+        self.send(Request::Rpm(rpm)).map_err(Into::into)
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        78.0 / 33.0
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        metadata(&self.remote.snapshot())
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.remote.snapshot().volume as f64
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) -> zbus::Result<()> {
+        self.send(Request::Volume(volume.max(0.0) as f32)).map_err(Into::into)
+    }
+
+    // Microseconds into the track.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        (self.remote.snapshot().position * 1e6) as i64
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        self.remote.snapshot().has_next()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        self.remote.snapshot().has_previous()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.remote.snapshot().track.is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+fn playback_status(snapshot: &Snapshot) -> &'static str {
+    match snapshot.status {
+        Status::Playing => "Playing",
+        Status::Paused => "Paused",
+        Status::Stopped => "Stopped",
+    }
+}
+
+fn track_id_of(snapshot: &Snapshot) -> String {
+    match (&snapshot.album, snapshot.track) {
+        (Some(album), Some(track)) => format!("/org/levari/album{}/track{}", album.index, track),
+        _ => NO_TRACK.to_string(),
+    }
+}
+
+fn metadata(snapshot: &Snapshot) -> HashMap<String, OwnedValue> {
+    let mut map = HashMap::new();
+    let mut insert = |key: &str, value: Value<'_>| {
+        if let Ok(value) = value.try_to_owned() {
+            map.insert(key.to_string(), value);
+        }
+    };
+    let track_id = track_id_of(snapshot);
+    if let Ok(path) = ObjectPath::try_from(track_id.as_str()) {
+        insert("mpris:trackid", Value::from(path));
+    }
+    let (Some(album), Some(track)) = (&snapshot.album, snapshot.current_track()) else {
+        return map;
+    };
+    insert("mpris:length", Value::from(track.duration as i64 * 1_000_000));
+    insert("xesam:title", Value::from(track.title.as_str()));
+    insert("xesam:album", Value::from(album.name.as_str()));
+    insert("xesam:trackNumber", Value::from(snapshot.track.unwrap_or(0) as i32 + 1));
+    insert("xesam:url", Value::from(file_url(&track.path)));
+    if let Some(ref cover) = album.cover {
+        insert("mpris:artUrl", Value::from(file_url(cover)));
+    }
+    map
+}
+
+// Every byte but the unreserved characters and `/` is percent-encoded, so
+// spaces, `#`, `%` and names that aren't UTF-8 make valid URLs too.
+pub fn file_url(path: &std::path::Path) -> String {
+    let mut url = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+// Compares snapshots and tells listeners about what changed. Position is
+// not signalled as it changes, clients extrapolate it, only jumps are sent
+// as `Seeked`.
+fn watch(conn: Connection, remote: Handle) {
+    let mut last = remote.snapshot();
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let now = remote.snapshot();
+        let mut changed: HashMap<&str, Value> = HashMap::new();
+        if now.status != last.status {
+            changed.insert("PlaybackStatus", Value::from(playback_status(&now)));
+            changed.insert("CanSeek", Value::from(now.track.is_some()));
+        }
+        if track_id_of(&now) != track_id_of(&last) || now.current_track() != last.current_track() {
+            changed.insert("Metadata", Value::from(metadata(&now)));
+            changed.insert("CanGoNext", Value::from(now.has_next()));
+            changed.insert("CanGoPrevious", Value::from(now.has_previous()));
+        }
+        if now.volume != last.volume {
+            changed.insert("Volume", Value::from(now.volume as f64));
+        }
+        if now.rpm != last.rpm {
+            changed.insert("Rate", Value::from(now.rpm as f64 / 33.0));
+        }
+        if !changed.is_empty() {
+            let body = (PLAYER_INTERFACE, changed, Vec::<&str>::new());
+            let sent = conn.emit_signal(None::<()>, OBJECT_PATH, PROPERTIES_INTERFACE, "PropertiesChanged", &body);
+            if sent.is_err() {
+                return;
+            }
+        }
+
+        let expected = match now.status {
+            Status::Playing => last.position + WATCH_INTERVAL.as_secs_f64() * now.rpm as f64 / 33.0,
+            _ => last.position,
+        };
+        let same_track = track_id_of(&now) == track_id_of(&last);
+        if now.track.is_some() && (now.position - expected).abs() > SEEK_TOLERANCE && same_track {
+            let position = (now.position * 1e6) as i64;
+            let _ = conn.emit_signal(None::<()>, OBJECT_PATH, PLAYER_INTERFACE, "Seeked", &(position,));
+        }
+        last = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        path::PathBuf,
+        process::{Child, Command, Stdio},
+    };
+
+    use zbus::blocking::Proxy;
+
+    use super::*;
+    use crate::remote::{AlbumSnapshot, Remote, TrackSnapshot};
+
+    // A private session bus, stopped when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        // None when there is no dbus-daemon to start.
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?).read_line(&mut address).ok()?;
+            Some(Self { daemon, address: address.trim().to_string() })
+        }
+
+        fn connect(&self) -> connection::Builder<'_> {
+            connection::Builder::address(self.address.as_str()).unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn playing() -> Snapshot {
+        let track = |title: &str| TrackSnapshot {
+            title: title.to_string(),
+            path: PathBuf::from(format!("/music/Some Album/{}.flac", title)),
+            duration: 200,
+            playable: true,
+        };
+        Snapshot {
+            status: Status::Playing,
+            album: Some(AlbumSnapshot {
+                index: 3,
+                name: "Some Album".to_string(),
+                path: PathBuf::from("/music/Some Album"),
+                cover: None,
+                tracks: vec![track("One"), track("Two #2")],
+            }),
+            track: Some(1),
+            position: 12.0,
+            volume: 1.0,
+            rpm: 33.0,
+        }
+    }
+
+    #[test]
+    fn serves_the_player_on_a_private_bus() {
+        let Some(bus) = Bus::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        let remote = Remote::new();
+        remote.publish(playing());
+        let _service = serve(bus.connect(), remote.handle()).unwrap();
+
+        let client = bus.connect().build().unwrap();
+        let player = Proxy::new(&client, BUS_NAME, OBJECT_PATH, PLAYER_INTERFACE).unwrap();
+        assert_eq!(player.get_property::<String>("PlaybackStatus").unwrap(), "Playing");
+        let metadata: HashMap<String, OwnedValue> = player.get_property("Metadata").unwrap();
+        let text = |key: &str| String::try_from(metadata[key].try_clone().unwrap()).unwrap();
+        assert_eq!(text("xesam:title"), "Two #2");
+        assert_eq!(text("xesam:album"), "Some Album");
+        assert_eq!(text("xesam:url"), "file:///music/Some%20Album/Two%20%232.flac");
+        assert_eq!(i64::try_from(&metadata["mpris:length"]).unwrap(), 200_000_000);
+
+        // The call only returns once the main loop has answered it.
+        let call = thread::spawn(move || player.call::<_, _, ()>("PlayPause", &()));
+        let (request, reply) = remote.recv_timeout(Duration::from_secs(5)).expect("no request arrived");
+        assert!(matches!(request, Request::PlayPause), "{:?}", request);
+        reply.send(Ok(())).unwrap();
+        call.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use zbus::{blocking::Connection, zvariant::Value};
+
+use crate::{config::NotificationsConfig, metadata, mpris::file_url};
+
+const DESTINATION: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+// Milliseconds on screen, -1 leaves it to the notification server.
+const EXPIRE: i32 = -1;
+
+pub enum Notification {
+    Insert {
+        album: String,
+        cover: Option<PathBuf>,
+        track_count: usize,
+        /// The track it starts at, its artist is read from the tags
+        track: Option<PathBuf>,
+    },
+    TrackChange {
+        album: String,
+        cover: Option<PathBuf>,
+        title: String,
+        /// Counting from 1
+        number: usize,
+        track: PathBuf,
+    },
+}
+
+// Sends notifications from its own thread, so a slow notification server
+// never holds up the player. Each one replaces the last instead of piling
+// up, and they are shown at most once per `min_interval`: while skipping
+// through an album only the track that was landed on is announced.
+pub struct Notifier {
+    sender: Sender<Notification>,
+    failures: Receiver<String>,
+}
+
+impl Notifier {
+    pub fn spawn(config: &NotificationsConfig) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::session()?;
+        let interval = Duration::from_secs_f64(config.min_interval);
+        let (sender, receiver) = mpsc::channel();
+        let (failed, failures) = mpsc::channel();
+        thread::Builder::new().name("notify".into()).spawn(move || run(conn, receiver, failed, interval))?;
+        Ok(Self { sender, failures })
+    }
+
+    pub fn send(&self, notification: Notification) {
+        let _ = self.sender.send(notification);
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = String> + '_ {
+        self.failures.try_iter()
+    }
+}
+
+fn run(conn: Connection, receiver: Receiver<Notification>, failed: Sender<String>, interval: Duration) {
+    // Servers that don't do markup would show the escapes literally.
+    let markup = conn
+        .call_method(Some(DESTINATION), OBJECT_PATH, Some(DESTINATION), "GetCapabilities", &())
+        .and_then(|reply| reply.body().deserialize::<Vec<String>>())
+        .is_ok_and(|caps| caps.iter().any(|c| c == "body-markup"));
+    let mut shown: Option<Instant> = None;
+    let mut id = 0;
+    let mut last_error = None;
+    while let Ok(mut notification) = receiver.recv() {
+        // Wait out the interval, anything newer replaces what was waiting.
+        while let Some(left) = shown.and_then(|at| interval.checked_sub(at.elapsed())) {
+            match receiver.recv_timeout(left) {
+                Ok(newer) => notification = newer,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        let (summary, lines, cover) = describe(notification);
+        let body = lines.iter().map(|l| if markup { escape(l) } else { l.clone() }).collect::<Vec<_>>().join("\n");
+        let icon = cover.map(|c| file_url(&c)).unwrap_or_default();
+        let mut hints: HashMap<&str, Value> = HashMap::new();
+        if !icon.is_empty() {
+            hints.insert("image-path", Value::from(icon.as_str()));
+        }
+        let actions: Vec<&str> = Vec::new();
+        let reply = conn.call_method(
+            Some(DESTINATION),
+            OBJECT_PATH,
+            Some(DESTINATION),
+            "Notify",
+            &("Levari", id, icon.as_str(), summary.as_str(), body.as_str(), actions, hints, EXPIRE),
+        );
+        shown = Some(Instant::now());
+        match reply.and_then(|reply| reply.body().deserialize::<u32>()) {
+            Ok(new_id) => {
+                id = new_id;
+                last_error = None;
+            }
+            Err(e) => {
+                let e = format!("Notification: {}", e);
+                if last_error.as_ref() != Some(&e) {
+                    let _ = failed.send(e.clone());
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+// Summary, body lines and icon.
+fn describe(notification: Notification) -> (String, Vec<String>, Option<PathBuf>) {
+    let artist = |track: &PathBuf| metadata::probe(track).ok().and_then(|info| info.tags.artist);
+    match notification {
+        Notification::Insert { album, cover, track_count, track } => {
+            let mut lines: Vec<String> = track.as_ref().and_then(artist).into_iter().collect();
+            lines.push(format!("{} tracks", track_count));
+            (album, lines, cover)
+        }
+        Notification::TrackChange { album, cover, title, number, track } => {
+            let mut lines: Vec<String> = artist(&track).into_iter().collect();
+            lines.push(album);
+            (format!("{}. {}", number, title), lines, cover)
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
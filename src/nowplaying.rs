@@ -0,0 +1,169 @@
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::config::{self, NowPlayingConfig};
+
+// What the player panel shows, and what goes into the now playing files.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NowPlaying {
+    /// "Playing", "Paused" or "Played through"
+    pub status: &'static str,
+    pub album: String,
+    pub album_path: PathBuf,
+    pub cover: Option<PathBuf>,
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    /// Counting from 1
+    pub track_number: Option<usize>,
+    pub track_count: usize,
+    /// Title of the next playable track
+    pub next: Option<String>,
+    /// Seconds into the track
+    pub elapsed: u64,
+    /// Seconds
+    pub duration: u64,
+    pub album_elapsed: u64,
+    pub album_duration: u64,
+    /// Percent
+    pub volume: u32,
+    pub rpm: f32,
+}
+
+// Keeps a text file and a JSON file up to date for streaming overlays and
+// the like. Files are only rewritten when their contents change, and
+// replaced with a rename so readers never see half a file.
+pub struct Writer {
+    text: Option<PathBuf>,
+    json: Option<PathBuf>,
+    format: String,
+    written: Option<(String, String)>,
+    last_error: Option<String>,
+}
+
+impl Writer {
+    // Nothing to do unless at least one file is configured.
+    pub fn new(config: &NowPlayingConfig) -> Option<Self> {
+        let text = config.text_file.as_deref().map(config::expand_home);
+        let json = config.json_file.as_deref().map(config::expand_home);
+        (text.is_some() || json.is_some()).then(|| Self {
+            text,
+            json,
+            format: config.format.clone(),
+            written: None,
+            last_error: None,
+        })
+    }
+
+    // Returns an error to report. The same one is only returned once, it
+    // would come back every tick otherwise.
+    pub fn update(&mut self, now: Option<&NowPlaying>) -> Option<String> {
+        let text = now.map(|n| format_text(&self.format, n)).unwrap_or_default();
+        let json = serde_json::to_string_pretty(&now).unwrap_or_default() + "\n";
+        let contents = (text, json);
+        if self.written.as_ref() == Some(&contents) {
+            return None;
+        }
+        let result = [(&self.text, &contents.0), (&self.json, &contents.1)]
+            .into_iter()
+            .filter_map(|(path, contents)| Some((path.as_ref()?, contents)))
+            .try_for_each(|(path, contents)| {
+                replace(path, contents).map_err(|e| format!("Now playing file {}: {}", path.display(), e))
+            });
+        match result {
+            Ok(()) => {
+                self.written = Some(contents);
+                self.last_error = None;
+                None
+            }
+            Err(e) if self.last_error.as_ref() == Some(&e) => None,
+            Err(e) => {
+                self.last_error = Some(e.clone());
+                Some(e)
+            }
+        }
+    }
+}
+
+// Fills in `{album}`, `{artist}`, `{track}`, `{track_number}`,
+// `{track_count}`, `{elapsed}`, `{duration}`, `{cover}` and `{status}` in
+// one pass, so braces in the names themselves are left alone. Unknown
+// placeholders are kept as they are.
+fn format_text(format: &str, now: &NowPlaying) -> String {
+    let time = |secs: u64| format!("{}:{:02}", secs / 60, secs % 60);
+    let mut text = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let value = match &rest[1..close] {
+                "album" => now.album.clone(),
+                "artist" => now.artist.clone().unwrap_or_default(),
+                "track" => now.track.clone().unwrap_or_default(),
+                "track_number" => now.track_number.map(|n| n.to_string()).unwrap_or_default(),
+                "track_count" => now.track_count.to_string(),
+                "elapsed" => time(now.elapsed),
+                "duration" => time(now.duration),
+                "cover" => now.cover.as_ref().map(|c| c.display().to_string()).unwrap_or_default(),
+                "status" => now.status.to_string(),
+                _ => return None,
+            };
+            Some((value, close))
+        });
+        match value {
+            Some((value, close)) => {
+                text.push_str(&value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+fn replace(path: &Path, contents: &str) -> io::Result<()> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn braces_in_names_are_not_filled_in() {
+        let now = NowPlaying {
+            status: "Playing",
+            album: "Live at {venue}".to_string(),
+            album_path: PathBuf::from("/music/Live"),
+            cover: None,
+            artist: Some("The {Braces}".to_string()),
+            track: Some("Song for {artist}".to_string()),
+            track_number: Some(3),
+            track_count: 12,
+            next: None,
+            elapsed: 65,
+            duration: 3600,
+            album_elapsed: 400,
+            album_duration: 4000,
+            volume: 100,
+            rpm: 33.0,
+        };
+        let format = "{artist} - {track} ({track_number}/{track_count}) on {album} {elapsed}/{duration} {foo} {status";
+        assert_eq!(
+            format_text(format, &now),
+            "The {Braces} - Song for {artist} (3/12) on Live at {venue} 1:05/60:00 {foo} {status"
+        );
+    }
+}
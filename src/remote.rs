@@ -0,0 +1,171 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+// How long a front end waits for the main loop to carry out a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Something from outside the terminal asking the player to act. Front ends
+// like MPRIS never touch `App`, they send these and read `Snapshot`s.
+#[derive(Debug, Clone)]
+pub enum Request {
+    Play,
+    Pause,
+    PlayPause,
+    /// Lifts the needle, i.e. ejects the album
+    Stop,
+    Next,
+    Previous,
+    /// Seconds to move from the current position, backwards if negative
+    Seek(f64),
+    /// Seconds from the start of the album
+    SeekAlbum(f64),
+    /// Track index and seconds from its start, ignored past its end
+    SetPosition(usize, f64),
+    /// Track index on the playing album
+    SkipTo(usize),
+    /// Album directory, looked up on the shelf
+    Insert(PathBuf),
+    /// Album directory, the playing or else the selected album if none
+    Bookmark(Option<PathBuf>),
+    /// 1.0 is 100%
+    Volume(f32),
+    Rpm(f32),
+    Quit,
+}
+
+pub type Reply = Result<(), String>;
+
+#[derive(Clone, Copy, PartialEq, Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    Stopped,
+    Playing,
+    Paused,
+}
+
+// What the front ends can see of the player, published by the main loop.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub status: Status,
+    pub album: Option<AlbumSnapshot>,
+    /// Index into `album.tracks` of the playing track
+    pub track: Option<usize>,
+    /// Seconds into the playing track
+    pub position: f64,
+    /// 1.0 is 100%
+    pub volume: f32,
+    pub rpm: f32,
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlbumSnapshot {
+    /// Position on the shelf
+    pub index: usize,
+    pub name: String,
+    pub path: PathBuf,
+    pub cover: Option<PathBuf>,
+    pub tracks: Vec<TrackSnapshot>,
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackSnapshot {
+    pub title: String,
+    pub path: PathBuf,
+    /// Seconds
+    pub duration: u64,
+    pub playable: bool,
+}
+
+impl Snapshot {
+    pub fn current_track(&self) -> Option<&TrackSnapshot> {
+        self.album.as_ref()?.tracks.get(self.track?)
+    }
+
+    pub fn has_next(&self) -> bool {
+        match (&self.album, self.track) {
+            (Some(album), Some(track)) => album.tracks[track + 1..].iter().any(|t| t.playable),
+            _ => false,
+        }
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.track.is_some()
+    }
+}
+
+type Envelope = (Request, Sender<Reply>);
+
+// The main loop's end: drains requests and publishes snapshots.
+pub struct Remote {
+    receiver: Receiver<Envelope>,
+    handle: Handle,
+}
+
+// The front ends' end, cheap to clone into every thread that needs one.
+#[derive(Clone)]
+pub struct Handle {
+    sender: Sender<Envelope>,
+    snapshot: Arc<Mutex<Snapshot>>,
+    /// Replaced as a whole when the library is rescanned
+    shelf: Arc<Mutex<Arc<Vec<AlbumSnapshot>>>>,
+}
+
+impl Remote {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self { receiver, handle: Handle { sender, snapshot: Arc::default(), shelf: Arc::default() } }
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    pub fn try_recv(&self) -> Option<(Request, Sender<Reply>)> {
+        self.receiver.try_recv().ok()
+    }
+
+    // Waits up to `timeout` for a request, for when there's nothing else to
+    // wait on.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<(Request, Sender<Reply>)> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    pub fn publish(&self, snapshot: Snapshot) {
+        *self.handle.snapshot.lock().unwrap_or_else(PoisonError::into_inner) = snapshot;
+    }
+
+    pub fn publish_shelf(&self, shelf: Vec<AlbumSnapshot>) {
+        *self.handle.shelf.lock().unwrap_or_else(PoisonError::into_inner) = Arc::new(shelf);
+    }
+}
+
+impl Handle {
+    // Blocks until the main loop has handled the request.
+    pub fn send(&self, request: Request) -> Reply {
+        let (reply, answer) = mpsc::channel();
+        self.sender.send((request, reply)).map_err(|_| "levari is shutting down".to_string())?;
+        match answer.recv_timeout(REPLY_TIMEOUT) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => Err("levari did not answer in time".to_string()),
+            Err(RecvTimeoutError::Disconnected) => Err("levari is shutting down".to_string()),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+    // Every album on the shelf, in shelf order. A new `Arc` means the shelf
+    // changed.
+    pub fn shelf(&self) -> Arc<Vec<AlbumSnapshot>> {
+        Arc::clone(&self.shelf.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
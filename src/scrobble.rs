@@ -0,0 +1,472 @@
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{config::ScrobbleConfig, history::Listen, metadata};
+
+// Tracks this short are never scrobbled.
+const MIN_DURATION: u64 = 30;
+// Half the track counts, but never more than this is needed.
+const MAX_REQUIRED: u64 = 240;
+// Listens per request, ListenBrainz takes up to 1000.
+const BATCH: usize = 100;
+const FIRST_RETRY: Duration = Duration::from_secs(60);
+const MAX_RETRY: Duration = Duration::from_secs(3600);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// One line of the queue file, everything needed to submit the listen
+// without the music at hand.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Scrobble {
+    /// Unix seconds when the track started
+    pub listened_at: u64,
+    pub artist: String,
+    pub track: String,
+    pub album: String,
+    /// Counting from 1
+    pub track_number: usize,
+    /// Seconds
+    pub duration: u64,
+}
+
+impl Scrobble {
+    // Listens of at least 30 second tracks that were heard for half their
+    // length or four minutes, whichever is less. The tags are preferred over
+    // the names on the shelf, and without an artist there is nothing to
+    // submit.
+    pub fn from_listen(listen: &Listen) -> Result<Option<Self>, String> {
+        if !counts(listen) {
+            return Ok(None);
+        }
+        let tags = metadata::probe(&listen.track_path).map(|info| info.tags).unwrap_or_default();
+        let Some(artist) = tags.artist.or(tags.album_artist) else {
+            return Err(format!("Not scrobbled, {} has no artist tag", listen.track_path.display()));
+        };
+        Ok(Some(Self {
+            listened_at: listen.started,
+            artist,
+            track: tags.title.unwrap_or_else(|| listen.track.clone()),
+            album: tags.album.unwrap_or_else(|| listen.album.clone()),
+            track_number: listen.track_number,
+            duration: listen.duration,
+        }))
+    }
+}
+
+fn counts(listen: &Listen) -> bool {
+    listen.duration > MIN_DURATION && listen.heard >= (listen.duration / 2).min(MAX_REQUIRED)
+}
+
+// The queue file, one JSON line per scrobble. Levari appends to it from the
+// player and removes what the submitter got through, and `levari scrobble`
+// may be at it from another process. Lock files next to it keep them apart:
+// one while the queue is read or written, one for the whole of a flush.
+pub struct Queue {
+    path: PathBuf,
+    lock: PathBuf,
+    submit_lock: PathBuf,
+}
+
+impl Queue {
+    pub fn new(path: PathBuf) -> Self {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        Self {
+            lock: path.with_file_name(format!(".{}.lock", name)),
+            submit_lock: path.with_file_name(format!(".{}.submit", name)),
+            path,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn push(&self, scrobble: &Scrobble) -> io::Result<()> {
+        let _lock = FileLock::acquire(&self.lock)?;
+        let mut line = serde_json::to_string(scrobble)?;
+        line.push('\n');
+        OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(line.as_bytes())
+    }
+
+    // Oldest first. Lines that don't parse are skipped.
+    pub fn load(&self) -> io::Result<Vec<Scrobble>> {
+        let _lock = FileLock::acquire(&self.lock)?;
+        self.read()
+    }
+
+    // Takes `done` out of the queue, keeping anything added in the meantime.
+    fn remove(&self, done: &[Scrobble]) -> io::Result<()> {
+        let _lock = FileLock::acquire(&self.lock)?;
+        let mut left = Vec::new();
+        let mut done = done.to_vec();
+        for scrobble in self.read()? {
+            match done.iter().position(|d| *d == scrobble) {
+                Some(i) => {
+                    done.swap_remove(i);
+                }
+                None => left.push(scrobble),
+            }
+        }
+        let mut text = String::new();
+        for scrobble in &left {
+            text += &serde_json::to_string(scrobble)?;
+            text.push('\n');
+        }
+        let name = self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = self.path.with_file_name(format!(".{}.tmp", name));
+        fs::write(&temp, text)?;
+        fs::rename(&temp, &self.path)
+    }
+
+    fn read(&self) -> io::Result<Vec<Scrobble>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Ok(text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// An exclusive `flock` on a file, released when dropped. It works between
+// processes and between threads alike, each acquire opens the file anew.
+struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    fn acquire(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        loop {
+            // SAFETY: the descriptor belongs to `file`, which outlives the call.
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                return Ok(Self { _file: file });
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+pub enum SubmitError {
+    /// Worth trying again later, e.g. offline or the server is down
+    Retry(String),
+    /// The server won't take these listens, ever
+    Rejected(String),
+}
+
+// Talks to `{url}/1/submit-listens` of ListenBrainz or anything that speaks
+// its API.
+pub struct Client {
+    endpoint: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    pub fn new(config: &ScrobbleConfig) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(TIMEOUT)
+            .user_agent(concat!("levari/", env!("CARGO_PKG_VERSION")))
+            .build();
+        Self {
+            endpoint: format!("{}/1/submit-listens", config.url.trim_end_matches('/')),
+            token: config.token.clone(),
+            agent,
+        }
+    }
+
+    pub fn submit(&self, scrobbles: &[Scrobble]) -> Result<(), SubmitError> {
+        let payload: Vec<_> = scrobbles
+            .iter()
+            .map(|s| {
+                json!({
+                    "listened_at": s.listened_at,
+                    "track_metadata": {
+                        "artist_name": s.artist,
+                        "track_name": s.track,
+                        "release_name": s.album,
+                        "additional_info": {
+                            "duration_ms": s.duration * 1000,
+                            "tracknumber": s.track_number,
+                            "media_player": "levari",
+                            "submission_client": "levari",
+                            "submission_client_version": env!("CARGO_PKG_VERSION"),
+                        },
+                    },
+                })
+            })
+            .collect();
+        let listen_type = if payload.len() == 1 { "single" } else { "import" };
+        let mut request = self.agent.post(&self.endpoint);
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Token {}", token));
+        }
+        match request.send_json(json!({ "listen_type": listen_type, "payload": payload })) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, response)) => {
+                // ListenBrainz explains itself in {"code": 400, "error": "..."}.
+                let reason = response
+                    .into_json::<serde_json::Value>()
+                    .ok()
+                    .and_then(|body| body.get("error")?.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                let message = format!("{} answered {} {}", self.endpoint, status, reason).trim_end().to_string();
+                // 400 is bad data, anything else (auth, rate limits, server
+                // errors) may well work later.
+                match status {
+                    400 => Err(SubmitError::Rejected(message)),
+                    _ => Err(SubmitError::Retry(message)),
+                }
+            }
+            // Transport errors name the URL already.
+            Err(e) => Err(SubmitError::Retry(e.to_string())),
+        }
+    }
+}
+
+// Submits the whole queue, oldest first, in batches. Rejected batches are
+// dropped and reported, the rest stops at the first batch that has to wait.
+// Returns how many were submitted and what went wrong. Only one flush runs
+// at a time, others wait and find the queue without what it submitted.
+pub fn flush(queue: &Queue, client: &Client) -> Result<usize, Box<dyn Error>> {
+    let _submitting = FileLock::acquire(&queue.submit_lock)?;
+    let mut submitted = 0;
+    let mut rejected = Vec::new();
+    for batch in queue.load()?.chunks(BATCH) {
+        match client.submit(batch) {
+            Ok(()) => submitted += batch.len(),
+            Err(SubmitError::Rejected(e)) => rejected.push(format!("Dropped {} scrobbles: {}", batch.len(), e)),
+            Err(SubmitError::Retry(e)) => return Err(e.into()),
+        }
+        queue.remove(batch)?;
+    }
+    if rejected.is_empty() {
+        Ok(submitted)
+    } else {
+        Err(rejected.join("; ").into())
+    }
+}
+
+// Submits the queue in the background: right away when something is
+// queued, and again after a growing pause while the server can't be
+// reached. Failures are collected for the error log.
+pub struct Scrobbler {
+    queue: Arc<Queue>,
+    wake: Sender<()>,
+    failures: Receiver<String>,
+}
+
+impl Scrobbler {
+    pub fn spawn(config: &ScrobbleConfig) -> Result<Self, Box<dyn Error>> {
+        let path = config.queue_file().ok_or("no queue_file set and no $HOME to put one in")?;
+        let queue = Arc::new(Queue::new(path));
+        let client = Client::new(config);
+        let (wake, woken) = mpsc::channel();
+        let (failed, failures) = mpsc::channel();
+        let worker_queue = Arc::clone(&queue);
+        thread::Builder::new().name("scrobble".into()).spawn(move || {
+            // Whatever was left over from last time goes out first.
+            let mut retry_at = Some(Instant::now());
+            let mut pause = FIRST_RETRY;
+            let mut last_error = None;
+            loop {
+                let woken = match retry_at {
+                    Some(at) => woken.recv_timeout(at.saturating_duration_since(Instant::now())),
+                    None => woken.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match woken {
+                    // Something new, but the server wasn't there a moment
+                    // ago, so wait for the retry.
+                    Ok(()) if retry_at.is_some_and(|at| at > Instant::now()) => continue,
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                match flush(&worker_queue, &client) {
+                    Ok(_) => {
+                        retry_at = None;
+                        pause = FIRST_RETRY;
+                        last_error = None;
+                    }
+                    Err(e) => {
+                        let e = format!("Scrobbling: {}", e);
+                        // The same error every retry only needs saying once.
+                        if last_error.as_ref() != Some(&e) {
+                            let _ = failed.send(e.clone());
+                            last_error = Some(e);
+                        }
+                        retry_at = Some(Instant::now() + pause);
+                        pause = (pause * 2).min(MAX_RETRY);
+                    }
+                }
+            }
+        })?;
+        Ok(Self { queue, wake, failures })
+    }
+
+    // Queues the listen if it counts as a scrobble. The queue is written
+    // here, so nothing is lost if levari quits before it is submitted.
+    pub fn scrobble(&self, listen: &Listen) -> Result<(), String> {
+        let Some(scrobble) = Scrobble::from_listen(listen)? else {
+            return Ok(());
+        };
+        self.queue
+            .push(&scrobble)
+            .map_err(|e| format!("Scrobble queue {}: {}", self.queue.path().display(), e))?;
+        let _ = self.wake.send(());
+        Ok(())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = String> + '_ {
+        self.failures.try_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        net::TcpListener,
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use tiny_http::{Response, Server};
+
+    use super::*;
+
+    fn listen(heard: u64, duration: u64) -> Listen {
+        Listen {
+            album: "Album".to_string(),
+            album_path: PathBuf::from("/nonexistent/Album"),
+            track: "Track".to_string(),
+            track_path: PathBuf::from("/nonexistent/Album/01 Track.flac"),
+            track_number: 1,
+            started: 1_700_000_000,
+            ended: 1_700_000_300,
+            heard,
+            duration,
+            rpm: 33.0,
+            finished: false,
+        }
+    }
+
+    // A queue of `count` scrobbles in a directory of its own.
+    fn queue(name: &str, count: usize) -> Queue {
+        let dir = env::temp_dir().join(format!("levari-scrobble-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        let queue = Queue::new(dir.join("scrobbles.jsonl"));
+        for i in 0..count {
+            let scrobble = Scrobble {
+                listened_at: 1_700_000_000 + i as u64 * 300,
+                artist: "Artist".to_string(),
+                track: format!("Track {}", i + 1),
+                album: "Album".to_string(),
+                track_number: i + 1,
+                duration: 300,
+            };
+            queue.push(&scrobble).unwrap();
+        }
+        queue
+    }
+
+    fn remove(queue: Queue) {
+        let _ = fs::remove_dir_all(queue.path.parent().unwrap());
+    }
+
+    fn client(url: String) -> Client {
+        Client::new(&ScrobbleConfig { enabled: true, url, token: Some("token".to_string()), queue_file: None })
+    }
+
+    // A ListenBrainz stand-in answering every submission with `status`.
+    // Counts the listens it was sent.
+    fn mock(status: u16) -> (String, Arc<AtomicUsize>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&received);
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                assert_eq!(request.url(), "/1/submit-listens");
+                let auth = request.headers().iter().find(|h| h.field.equiv("Authorization"));
+                let auth = auth.map(|h| h.value.to_string());
+                assert_eq!(auth.as_deref(), Some("Token token"));
+                let body: serde_json::Value = serde_json::from_reader(request.as_reader()).unwrap();
+                counter.fetch_add(body["payload"].as_array().unwrap().len(), Ordering::SeqCst);
+                let answer = match status {
+                    200 => r#"{"status": "ok"}"#,
+                    _ => r#"{"code": 400, "error": "Invalid listen"}"#,
+                };
+                let _ = request.respond(Response::from_string(answer).with_status_code(status));
+            }
+        });
+        (url, received)
+    }
+
+    #[test]
+    fn half_the_track_or_four_minutes_of_tracks_over_30_seconds() {
+        assert!(!counts(&listen(30, 30)));
+        assert!(!counts(&listen(19, 40)));
+        assert!(counts(&listen(20, 40)));
+        assert!(!counts(&listen(239, 1000)));
+        assert!(counts(&listen(240, 1000)));
+        assert_eq!(Scrobble::from_listen(&listen(19, 40)), Ok(None));
+        // Long enough, but there are no tags to find an artist in.
+        assert!(Scrobble::from_listen(&listen(20, 40)).is_err());
+    }
+
+    #[test]
+    fn submitted_scrobbles_leave_the_queue() {
+        let (url, received) = mock(200);
+        let queue = queue("submitted", 3);
+        assert_eq!(flush(&queue, &client(url)).unwrap(), 3);
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+        assert!(queue.load().unwrap().is_empty());
+
+        remove(queue);
+    }
+
+    #[test]
+    fn rejected_scrobbles_are_dropped() {
+        let (url, received) = mock(400);
+        let queue = queue("rejected", 2);
+        let error = flush(&queue, &client(url)).unwrap_err().to_string();
+        assert!(error.starts_with("Dropped 2 scrobbles"), "{}", error);
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+        assert!(queue.load().unwrap().is_empty());
+
+        remove(queue);
+    }
+
+    #[test]
+    fn scrobbles_stay_queued_while_the_server_is_unavailable() {
+        let (url, received) = mock(503);
+        let queue = queue("unavailable", 2);
+        assert!(flush(&queue, &client(url)).is_err());
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+        assert_eq!(queue.load().unwrap().len(), 2);
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        assert!(flush(&queue, &client(format!("http://{}", closed))).is_err());
+        assert_eq!(queue.load().unwrap().len(), 2);
+
+        remove(queue);
+    }
+}
@@ -0,0 +1,37 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+// What survives a restart when a state file is configured.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct State {
+    /// Mark letter to album directory
+    pub marks: BTreeMap<String, PathBuf>,
+}
+
+impl State {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e).into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+
+    // Writes to a temporary file first so a crash never leaves half a file.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
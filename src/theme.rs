@@ -0,0 +1,147 @@
+use std::{env, error::Error};
+
+use tui::style::{Color, Modifier, Style};
+
+use crate::config::ThemeConfig;
+
+pub struct Theme {
+    pub title: Style,
+    pub accent: Style,
+    pub border: Style,
+    pub focused: Style,
+    pub muted: Style,
+    pub highlight: Style,
+}
+
+impl Theme {
+    fn default_colors() -> Self {
+        Self {
+            title: Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            accent: Style::default().fg(Color::Magenta),
+            border: Style::default().fg(Color::Yellow),
+            focused: Style::default().fg(Color::Magenta),
+            muted: Style::default().fg(Color::DarkGray),
+            highlight: Style::default().fg(Color::Magenta),
+        }
+    }
+
+    fn high_contrast() -> Self {
+        Self {
+            title: Style::default().fg(Color::White).add_modifier(Modifier::BOLD),
+            accent: Style::default().fg(Color::LightYellow).add_modifier(Modifier::BOLD),
+            border: Style::default().fg(Color::White),
+            focused: Style::default().fg(Color::LightYellow).add_modifier(Modifier::BOLD),
+            muted: Style::default().fg(Color::Gray),
+            highlight: Style::default().fg(Color::Black).bg(Color::LightYellow),
+        }
+    }
+
+    // No colours at all, focus and selection are shown with text attributes.
+    fn mono() -> Self {
+        Self {
+            title: Style::default().add_modifier(Modifier::BOLD),
+            accent: Style::default().add_modifier(Modifier::BOLD),
+            border: Style::default(),
+            focused: Style::default().add_modifier(Modifier::BOLD),
+            muted: Style::default().add_modifier(Modifier::DIM),
+            highlight: Style::default().add_modifier(Modifier::REVERSED),
+        }
+    }
+
+    // Picks the named theme, falling back to `mono` when NO_COLOR is set and
+    // no theme was asked for, then applies any colour overrides.
+    pub fn from_config(config: &ThemeConfig) -> Result<Self, Box<dyn Error>> {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let name = match config.name.as_deref() {
+            Some(name) => name,
+            None if no_color => "mono",
+            None => "default",
+        };
+        let mut theme = match name {
+            "default" => Self::default_colors(),
+            "high-contrast" => Self::high_contrast(),
+            "mono" => Self::mono(),
+            other => {
+                return Err(format!("unknown theme '{}', expected default, high-contrast or mono", other).into())
+            }
+        };
+        if let Some(ref c) = config.title {
+            theme.title = theme.title.fg(parse_color(c)?);
+        }
+        if let Some(ref c) = config.accent {
+            let color = parse_color(c)?;
+            theme.accent = theme.accent.fg(color);
+            theme.focused = theme.focused.fg(color);
+            theme.highlight = theme.highlight.fg(color);
+        }
+        if let Some(ref c) = config.border {
+            theme.border = theme.border.fg(parse_color(c)?);
+        }
+        if let Some(ref c) = config.muted {
+            theme.muted = theme.muted.fg(parse_color(c)?);
+        }
+        Ok(theme)
+    }
+
+    pub fn border(&self, focused: bool) -> Style {
+        if focused {
+            self.focused
+        } else {
+            self.border
+        }
+    }
+}
+
+// Accepts colour names ("light-blue"), "#rrggbb" and 256-colour indexes.
+fn parse_color(value: &str) -> Result<Color, Box<dyn Error>> {
+    let name: String = value.to_ascii_lowercase().chars().filter(|c| !matches!(c, '-' | '_' | ' ')).collect();
+    let color = match name.as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        hex if hex.starts_with('#') => {
+            // Only ASCII hex digits, so the slices below fall on characters.
+            if hex.len() != 7 || !hex[1..].bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("invalid colour '{}'", value).into());
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default();
+            Color::Rgb(channel(1), channel(3), channel(5))
+        }
+        index => match index.parse::<u8>() {
+            Ok(i) => Color::Indexed(i),
+            Err(_) => return Err(format!("invalid colour '{}'", value).into()),
+        },
+    };
+    Ok(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours() {
+        assert_eq!(parse_color("Light-Blue").ok(), Some(Color::LightBlue));
+        assert_eq!(parse_color("dark_grey").ok(), Some(Color::DarkGray));
+        assert_eq!(parse_color("#1E2f3a").ok(), Some(Color::Rgb(0x1e, 0x2f, 0x3a)));
+        assert_eq!(parse_color("208").ok(), Some(Color::Indexed(208)));
+        for bad in ["#12345", "#1234567", "#12345g", "#+1+2+3", "#1é234", "#ééé", "256", "pink", ""] {
+            let error = parse_color(bad).expect_err(bad).to_string();
+            assert_eq!(error, format!("invalid colour '{}'", bad));
+        }
+    }
+}